@@ -84,6 +84,9 @@ mod abci {
             self.staking.slash_fraction_double_sign = (Amount::new(1) / Amount::new(20))?;
             self.staking.min_self_delegation_min = 0;
 
+            self.bitcoin.config.withdrawal_fee = crate::bitcoin::WITHDRAWAL_FEE;
+            self.bitcoin.config.dust_threshold = crate::bitcoin::DUST_THRESHOLD;
+
             let old_home_path = nomicv2::orga::abci::Node::<()>::home(nomicv2::app::CHAIN_ID);
             exec_migration(self, old_home_path.join("merk"), &[0, 1, 0])?;
 
@@ -484,3 +487,39 @@ impl RewardTimer {
         true
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::signatory::SignatorySet;
+    use crate::bitcoin::test_utils::mem_store;
+
+    /// Builds the app state over an in-memory store, so handlers can be called
+    /// and queried directly without running a Tendermint node.
+    fn in_process_app() -> InnerApp {
+        let store = mem_store();
+        InnerApp::create(store, Default::default()).unwrap()
+    }
+
+    #[test]
+    fn peg_query() {
+        let mut app = in_process_app();
+
+        let headers = &app.bitcoin.headers;
+        assert_eq!(headers.height().unwrap(), headers.trusted_height());
+
+        app.bitcoin
+            .checkpoints
+            .push_mock(SignatorySet::mock(&[10, 30, 20]))
+            .unwrap();
+        let voting_powers: Vec<_> = app
+            .bitcoin
+            .signatory_set()
+            .unwrap()
+            .into_iter()
+            .map(|(_, voting_power)| voting_power)
+            .collect();
+        assert_eq!(voting_powers, vec![10, 30, 20]);
+        assert_eq!(app.bitcoin.value_locked().unwrap(), 0);
+    }
+}
@@ -9,7 +9,10 @@ use std::path::PathBuf;
 use bitcoincore_rpc_async::{Auth, Client as BtcClient};
 use clap::Parser;
 use futures::executor::block_on;
-use nomic::bitcoin::{relayer::Relayer, signer::Signer};
+use nomic::bitcoin::{
+    relayer::{Relayer, SharedRelayerStatus},
+    signer::Signer,
+};
 use nomic::error::Result;
 use nomicv2::command::Opts as LegacyOpts;
 use orga::prelude::*;
@@ -733,10 +736,13 @@ impl RelayerCmd {
     }
 
     async fn run(&self) -> Result<()> {
+        let status = SharedRelayerStatus::default();
         let create_relayer = async || {
             let btc_client = self.btc_client().await.unwrap();
 
-            Relayer::new(btc_client, app_client()).await
+            Relayer::new(btc_client, app_client())
+                .await
+                .with_status(status.clone())
         };
 
         let mut relayer = create_relayer().await;
@@ -86,6 +86,9 @@ impl<T: Decodable> Decode for Adapter<T> {
         let decoded_bytes = Decodable::consensus_decode(input);
         match decoded_bytes {
             Ok(inner) => Ok(Self { inner }),
+            // surface reader failures as-is so they aren't mistaken for
+            // invalid data
+            Err(bitcoin::consensus::encode::Error::Io(err)) => Err(err.into()),
             Err(_) => {
                 let std_e = std::io::Error::new(
                     std::io::ErrorKind::Other,
@@ -122,3 +125,70 @@ impl<T> Call for Adapter<T> {
 }
 
 impl<T: Copy> Copy for Adapter<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::BlockHeader;
+
+    struct FailingReader<'a>(&'a [u8]);
+
+    impl Read for FailingReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.0.is_empty() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::BrokenPipe,
+                    "connection reset",
+                ));
+            }
+
+            let n = buf.len().min(self.0.len());
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn decode_reader_error() {
+        let bytes = [0; 40];
+        let err = Adapter::<BlockHeader>::decode(FailingReader(&bytes)).unwrap_err();
+        assert!(err.to_string().contains("connection reset"));
+    }
+
+    #[test]
+    fn decode_invalid_bytes() {
+        // version followed by a segwit marker with an unsupported flag
+        let bytes = [1, 0, 0, 0, 0, 2];
+        let err = Adapter::<bitcoin::Transaction>::decode(bytes.as_slice()).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Failed to decode bitcoin primitive"));
+    }
+
+    #[test]
+    fn transaction_witness_round_trip() {
+        let tx = bitcoin::Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![bitcoin::TxIn {
+                previous_output: Default::default(),
+                script_sig: Default::default(),
+                sequence: u32::MAX,
+                witness: bitcoin::Witness::from_vec(vec![vec![1, 2, 3], vec![4; 33]]),
+            }],
+            output: vec![bitcoin::TxOut {
+                value: 1_000,
+                script_pubkey: Default::default(),
+            }],
+        };
+        assert_ne!(tx.txid().as_hash(), tx.wtxid().as_hash());
+
+        let bytes = Adapter::new(tx.clone()).encode().unwrap();
+        let decoded = Adapter::<bitcoin::Transaction>::decode(bytes.as_slice()).unwrap();
+
+        assert_eq!(decoded.txid(), tx.txid());
+        assert_eq!(decoded.wtxid(), tx.wtxid());
+        assert_eq!(decoded.input[0].witness, tx.input[0].witness);
+    }
+}
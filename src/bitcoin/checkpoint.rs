@@ -2,7 +2,7 @@ use super::{
     adapter::Adapter,
     signatory::SignatorySet,
     threshold_sig::{LengthVec, Signature, ThresholdSig},
-    ConsensusKey, Xpub,
+    Config, ConsensusKey, Xpub,
 };
 use crate::error::{Error, Result};
 use bitcoin::blockdata::transaction::EcdsaSighashType;
@@ -105,10 +105,14 @@ impl Input {
     }
 
     pub fn est_vsize(&self) -> u64 {
-        self.est_witness_vsize + 40
+        est_input_vsize(self.est_witness_vsize)
     }
 }
 
+pub fn est_input_vsize(est_witness_vsize: u64) -> u64 {
+    est_witness_vsize + 40
+}
+
 pub type Output = Adapter<bitcoin::TxOut>;
 
 #[derive(State, Call, Query, Client, Debug)]
@@ -521,7 +525,11 @@ impl CheckpointQueue {
         Ok(BuildingCheckpointMut(last))
     }
 
-    pub fn maybe_step(&mut self, sig_keys: &Map<ConsensusKey, Xpub>) -> Result<()> {
+    pub fn maybe_step(
+        &mut self,
+        sig_keys: &Map<ConsensusKey, Xpub>,
+        config: &Config,
+    ) -> Result<()> {
         #[cfg(not(feature = "full"))]
         unimplemented!();
 
@@ -557,7 +565,7 @@ impl CheckpointQueue {
                 }
             }
 
-            if self.maybe_push(sig_keys)?.is_none() {
+            if self.maybe_push(sig_keys, config)?.is_none() {
                 return Ok(());
             }
 
@@ -596,6 +604,7 @@ impl CheckpointQueue {
     fn maybe_push(
         &mut self,
         sig_keys: &Map<ConsensusKey, Xpub>,
+        config: &Config,
     ) -> Result<Option<BuildingCheckpointMut>> {
         #[cfg(not(feature = "full"))]
         unimplemented!();
@@ -607,7 +616,12 @@ impl CheckpointQueue {
                 index += 1;
             }
 
-            let sigset = SignatorySet::from_validator_ctx(index, sig_keys)?;
+            let sigset = SignatorySet::from_validator_ctx(
+                index,
+                sig_keys,
+                config.max_signatories,
+                config.min_signatory_voting_power,
+            )?;
 
             if sigset.possible_vp() == 0 {
                 return Ok(None);
@@ -661,3 +675,57 @@ impl CheckpointQueue {
         Ok(self.get(index)?.sigset.clone())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::test_utils::mem_store;
+
+    impl CheckpointQueue {
+        pub(crate) fn push_mock(&mut self, sigset: SignatorySet) -> Result<()> {
+            if !self.queue.is_empty() {
+                self.index += 1;
+            }
+
+            self.queue.push_back(Default::default())?;
+            self.building_mut()?.sigset = sigset;
+
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn flush_and_recreate() {
+        let store = mem_store();
+        let mut queue = CheckpointQueue::create(store.clone(), Default::default()).unwrap();
+
+        let sigset = SignatorySet::mock(&[10, 30, 20]);
+        queue.queue.push_back(Default::default()).unwrap();
+        let mut building = queue.building_mut().unwrap();
+        building.sigset = sigset.clone();
+        building
+            .push_input(Default::default(), &sigset, Address::NULL, 50_000)
+            .unwrap();
+        drop(building);
+
+        let data = State::flush(queue).unwrap();
+        let queue = CheckpointQueue::create(store, data).unwrap();
+
+        let building = queue.building().unwrap();
+        let voting_powers: Vec<_> = building
+            .sigset
+            .iter()
+            .map(|signatory| signatory.voting_power)
+            .collect();
+        assert_eq!(voting_powers, vec![10, 30, 20]);
+        assert_eq!(building.sigset.present_vp(), 60);
+
+        assert_eq!(building.inputs.len(), 1);
+        let input = building.inputs.get(0).unwrap().unwrap();
+        assert_eq!(input.amount, 50_000);
+        assert_eq!(input.sigs.len(), 3);
+        for signatory in sigset.iter() {
+            assert!(input.sigs.contains_key(signatory.pubkey).unwrap());
+        }
+    }
+}
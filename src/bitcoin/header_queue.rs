@@ -2,13 +2,17 @@ use crate::bitcoin::adapter::Adapter;
 use crate::error::{Error, Result};
 use bitcoin::blockdata::block::BlockHeader;
 use bitcoin::consensus::Encodable;
+use bitcoin::util::merkleblock::PartialMerkleTree;
 use bitcoin::util::uint::Uint256;
 use bitcoin::BlockHash;
 use bitcoin::TxMerkleNode;
+use bitcoin::Txid;
 use orga::call::Call;
 use orga::client::Client;
 use orga::collections::Deque;
+use orga::context::GetContext;
 use orga::encoding as ed;
+use orga::plugins::Time;
 use orga::prelude::*;
 use orga::query::Query;
 use orga::state::State;
@@ -19,6 +23,7 @@ use orga::Result as OrgaResult;
 const MAX_LENGTH: u64 = 4032;
 const MAX_RELAY: u64 = 25;
 const MAX_TIME_INCREASE: u32 = 2 * 60 * 60;
+const MAX_FUTURE_DRIFT: u32 = 2 * 60 * 60;
 const RETARGET_INTERVAL: u32 = 2016;
 const TARGET_SPACING: u32 = 10 * 60;
 const TARGET_TIMESPAN: u32 = RETARGET_INTERVAL * TARGET_SPACING;
@@ -87,7 +92,14 @@ impl WrappedHeader {
     }
 
     fn validate_pow(&self, required_target: &Uint256) -> Result<BlockHash> {
-        Ok(self.header.validate_pow(required_target)?)
+        match self.header.validate_pow(required_target) {
+            Ok(hash) => Ok(hash),
+            Err(bitcoin::Error::BlockBadProofOfWork) => Err(Error::Header(format!(
+                "Header at height {} failed PoW validation",
+                self.height
+            ))),
+            Err(err) => Err(err.into()),
+        }
     }
 }
 
@@ -185,6 +197,8 @@ impl WorkHeader {
 pub struct Config {
     pub max_length: u64,
     pub max_time_increase: u32,
+    /// How far in seconds a header's timestamp may be ahead of network time.
+    pub max_future_drift: u32,
     pub trusted_height: u32,
     pub retarget_interval: u32,
     pub target_spacing: u32,
@@ -214,6 +228,7 @@ impl Config {
         Self {
             max_length: MAX_LENGTH,
             max_time_increase: MAX_TIME_INCREASE,
+            max_future_drift: MAX_FUTURE_DRIFT,
             trusted_height: height,
             retarget_interval: RETARGET_INTERVAL,
             target_spacing: TARGET_SPACING,
@@ -237,6 +252,7 @@ impl Config {
         Self {
             max_length: MAX_LENGTH,
             max_time_increase: MAX_TIME_INCREASE,
+            max_future_drift: MAX_FUTURE_DRIFT,
             retarget_interval: RETARGET_INTERVAL,
             target_spacing: TARGET_SPACING,
             target_timespan: TARGET_TIMESPAN,
@@ -248,6 +264,24 @@ impl Config {
             network: bitcoin::Network::Testnet,
         }
     }
+
+    #[cfg(test)]
+    pub(crate) fn regtest(trusted_header: &BlockHeader, trusted_height: u32) -> Self {
+        Self {
+            max_length: 2000,
+            max_time_increase: 8 * 60 * 60,
+            max_future_drift: 2 * 60 * 60,
+            trusted_height,
+            retarget_interval: 2016,
+            target_spacing: 10 * 60,
+            target_timespan: 2016 * (10 * 60),
+            max_target: 0x207fffff,
+            retargeting: false,
+            min_difficulty_blocks: false,
+            encoded_trusted_header: Adapter::new(*trusted_header).encode().unwrap(),
+            network: bitcoin::Network::Regtest,
+        }
+    }
 }
 
 #[derive(Call, Query, Client)]
@@ -270,19 +304,7 @@ impl State for HeaderQueue {
             config: Config::mainnet(),
         };
 
-        let height = match queue.height() {
-            Ok(height) => height,
-            Err(err) => return Err(OrgaError::App(err.to_string())),
-        };
-
-        if height == 0 {
-            let decoded_adapter: Adapter<BlockHeader> =
-                Decode::decode(queue.config.encoded_trusted_header.as_slice())?;
-            let wrapped_header = WrappedHeader::new(decoded_adapter, queue.config.trusted_height);
-            let work_header = WorkHeader::new(wrapped_header.clone(), wrapped_header.work());
-            queue.current_work = Adapter::new(work_header.work());
-            queue.deque.push_front(work_header.into())?;
-        }
+        queue.seed_trusted_header()?;
 
         Ok(queue)
     }
@@ -305,50 +327,117 @@ impl HeaderQueue {
     pub fn add(&mut self, headers: HeaderList) -> Result<()> {
         super::exempt_from_fee()?;
 
-        let headers: Vec<_> = headers.into();
+        let now = self.context::<Time>().map(|time| time.seconds as u64);
+
+        self.add_relayed(headers.into(), now)
+            .map_err(|err| OrgaError::App(err.to_string()).into())
+    }
 
+    /// Adds a batch relayed through `add`. Since relaying is exempt from fees,
+    /// a batch which contains no new headers is rejected rather than accepted
+    /// as a free no-op.
+    fn add_relayed(&mut self, headers: Vec<WrappedHeader>, now: Option<u64>) -> Result<()> {
         if headers.len() as u64 > MAX_RELAY {
-            return Err(
-                OrgaError::App("Exceeded maximum amount of relayed headers".to_string()).into(),
-            );
+            return Err(Error::Header(
+                "Exceeded maximum amount of relayed headers".into(),
+            ));
         }
 
-        self.add_into_iter(headers)
-            .map_err(|err| OrgaError::App(err.to_string()).into())
+        if self.add_with_time(headers, now)? == 0 {
+            return Err(Error::Header(
+                "Passed header list contains no new headers".into(),
+            ));
+        }
+
+        Ok(())
     }
 
     pub fn add_into_iter<T>(&mut self, headers: T) -> Result<()>
     where
         T: IntoIterator<Item = WrappedHeader>,
     {
-        let headers: Vec<WrappedHeader> = headers.into_iter().collect();
+        self.add_with_time(headers, None)?;
+        Ok(())
+    }
+
+    /// Adds headers to the queue. If `now` is given, headers more than
+    /// `max_future_drift` seconds ahead of it are rejected, as Bitcoin does
+    /// against network-adjusted time. Returns the number of headers which were
+    /// not already in the queue.
+    fn add_with_time<T>(&mut self, headers: T, now: Option<u64>) -> Result<usize>
+    where
+        T: IntoIterator<Item = WrappedHeader>,
+    {
+        let mut headers: Vec<WrappedHeader> = headers.into_iter().collect();
         let current_height = self.height()?;
 
-        let first = headers
-            .first()
-            .ok_or_else(|| Error::Header("Passed header list empty".into()))?;
+        if headers.is_empty() {
+            return Err(Error::Header("Passed header list empty".into()));
+        }
 
-        let mut removed_work = Uint256::default();
-        if first.height <= current_height {
-            let first_replaced = self
-                .get_by_height(first.height)?
+        // skip headers we already have, e.g. when a relayer resends an
+        // overlapping batch
+        let mut known = 0;
+        for header in headers.iter() {
+            if header.height > current_height {
+                break;
+            }
+
+            let existing = self
+                .get_by_height(header.height)?
                 .ok_or_else(|| Error::Header("Header not found".into()))?;
+            if existing.block_hash() != header.block_hash() {
+                break;
+            }
+
+            known += 1;
+        }
+        headers.drain(..known);
 
-            if first_replaced.block_hash() == first.block_hash() {
-                return Err(Error::Header("Provided redudant header.".into()));
+        let first_height = match headers.first() {
+            Some(first) => first.height,
+            None => return Ok(0),
+        };
+        if first_height == 0 {
+            return Err(Error::Header("Headers must start after height 0".into()));
+        }
+
+        let mut removed = vec![];
+        if first_height <= current_height {
+            // cheaply reject branches which claim less work before touching the
+            // queue. on equal work, the first-seen chain wins. the claimed work
+            // is checked against the required targets during verification.
+            let removed_work = self.work_since(first_height)?;
+            let added_work = headers
+                .iter()
+                .fold(Uint256::default(), |work, header| work + header.work());
+            if added_work <= removed_work {
+                return Err(Error::Header(
+                    "New best chain must include more work than old best chain.".into(),
+                ));
             }
 
-            removed_work = self.pop_back_to(first.height)?;
+            removed = self.pop_back_to(first_height)?;
         }
 
-        let added_work = self.verify_and_add_headers(&headers)?;
+        if let Err(err) = self.verify_and_add_headers(&headers, now) {
+            // put back the previous chain so a rejected batch leaves the queue
+            // unchanged
+            self.pop_back_to(first_height)?;
+            for header in removed.into_iter().rev() {
+                self.current_work = Adapter::new(*self.current_work + header.work());
+                self.deque.push_back(header.into())?;
+            }
 
-        if added_work <= removed_work {
-            return Err(Error::Header(
-                "New best chain must include more work than old best chain.".into(),
-            ));
+            return Err(err);
         }
 
+        self.prune()?;
+
+        Ok(headers.len())
+    }
+
+    fn prune(&mut self) -> Result<()> {
         while self.len() > self.config.max_length {
             let header = match self.deque.pop_front()? {
                 Some(inner) => inner,
@@ -364,14 +453,81 @@ impl HeaderQueue {
         Ok(())
     }
 
-    fn verify_and_add_headers(&mut self, headers: &[WrappedHeader]) -> Result<Uint256> {
+    /// Appends headers ending at the configured trusted header without checking their work.
+    pub fn import_trusted_batch<T>(&mut self, headers: T, up_to_height: u32) -> Result<()>
+    where
+        T: IntoIterator<Item = WrappedHeader>,
+    {
+        if up_to_height > self.config.trusted_height {
+            return Err(Error::Header(
+                "Trusted batch height is above the configured trusted height".into(),
+            ));
+        }
+
+        let trusted_header: Adapter<BlockHeader> =
+            Decode::decode(self.config.encoded_trusted_header.as_slice())
+                .map_err(OrgaError::from)?;
+
+        let headers: Vec<WrappedHeader> = headers.into_iter().collect();
+        let (mut prev_height, mut prev_hash) = self
+            .tip()?
+            .ok_or_else(|| Error::Header("Queue does not contain any headers".into()))?;
+
+        for header in headers.iter() {
+            if header.height() > up_to_height {
+                return Err(Error::Header(
+                    "Trusted header is above the trusted batch height".into(),
+                ));
+            }
+
+            if header.height() != prev_height + 1 {
+                return Err(Error::Header("Non-consecutive headers passed".into()));
+            }
+
+            if header.prev_blockhash() != prev_hash {
+                return Err(Error::Header(
+                    "Passed header references incorrect previous block hash".into(),
+                ));
+            }
+
+            prev_height = header.height();
+            prev_hash = header.block_hash();
+        }
+
+        match headers.last() {
+            Some(last) if last.height() == self.config.trusted_height => {
+                if last.block_hash() != trusted_header.block_hash() {
+                    return Err(Error::Header(
+                        "Trusted batch does not match the configured trusted header".into(),
+                    ));
+                }
+            }
+            _ => {
+                return Err(Error::Header(
+                    "Trusted batch must end at the configured trusted height".into(),
+                ))
+            }
+        }
+
+        for header in headers {
+            let chain_work = *self.current_work + header.work();
+            self.deque
+                .push_back(WorkHeader::new(header, chain_work).into())?;
+            self.current_work = Adapter::new(chain_work);
+        }
+
+        self.prune()
+    }
+
+    fn verify_and_add_headers(
+        &mut self,
+        headers: &[WrappedHeader],
+        now: Option<u64>,
+    ) -> Result<Uint256> {
         let first_height = headers
             .first()
             .ok_or_else(|| Error::Header("Passed header list is empty".into()))?
             .height;
-        if first_height == 0 {
-            return Err(Error::Header("Headers must start after height 0".into()));
-        }
 
         let prev_header = [self
             .get_by_height(first_height - 1)?
@@ -397,6 +553,14 @@ impl HeaderQueue {
                 self.validate_time(header)?;
             }
 
+            if let Some(now) = now {
+                if header.time() as u64 > now + self.config.max_future_drift as u64 {
+                    return Err(Error::Header(
+                        "Header timestamp is too far in the future".into(),
+                    ));
+                }
+            }
+
             let target = self.get_next_target(header, prev_header)?;
             header.validate_pow(&target)?;
 
@@ -497,19 +661,35 @@ impl HeaderQueue {
         }
     }
 
-    fn pop_back_to(&mut self, height: u32) -> Result<Uint256> {
+    fn work_since(&self, height: u32) -> Result<Uint256> {
         let mut work = Uint256::default();
 
+        for i in height..=self.height()? {
+            let header = self
+                .get_by_height(i)?
+                .ok_or_else(|| Error::Header("Header not found".into()))?;
+
+            work = work + header.work();
+        }
+
+        Ok(work)
+    }
+
+    fn pop_back_to(&mut self, height: u32) -> Result<Vec<WorkHeader>> {
+        let mut removed = vec![];
+
         while self.height()? >= height {
             let header = self
                 .deque
                 .pop_back()?
-                .ok_or_else(|| Error::Header("Removed all headers".into()))?;
+                .ok_or_else(|| Error::Header("Removed all headers".into()))?
+                .into_inner();
 
-            work = work + header.work();
+            self.current_work = Adapter::new(*self.current_work - header.work());
+            removed.push(header);
         }
 
-        Ok(work)
+        Ok(removed)
     }
 
     fn validate_time(&self, current_header: &WrappedHeader) -> Result<()> {
@@ -538,15 +718,6 @@ impl HeaderQueue {
             return Err(Error::Header("Header contains an invalid timestamp".into()));
         }
 
-        // if max(current_header.time(), previous_header.time())
-        //     - min(current_header.time(), previous_header.time())
-        //     > self.config.max_time_increase
-        // {
-        //     return Err(Error::Header(
-        //         "Timestamp is too far ahead of previous timestamp".into(),
-        //     ));
-        // }
-
         Ok(())
     }
 
@@ -566,6 +737,13 @@ impl HeaderQueue {
         }
     }
 
+    pub fn tip(&self) -> Result<Option<(u32, BlockHash)>> {
+        Ok(self
+            .deque
+            .back()?
+            .map(|header| (header.height(), header.block_hash())))
+    }
+
     pub fn len(&self) -> u64 {
         self.deque.len()
     }
@@ -593,6 +771,42 @@ impl HeaderQueue {
         }
     }
 
+    /// Checks that `proof` proves the inclusion of the transaction with the
+    /// given txid in the header at `height`.
+    pub fn verify_tx_proof(
+        &self,
+        height: u32,
+        proof: &PartialMerkleTree,
+        txid: Txid,
+    ) -> Result<()> {
+        let header = self
+            .get_by_height(height)?
+            .ok_or_else(|| Error::Header("Invalid bitcoin block height".into()))?;
+
+        let mut txids = vec![];
+        let mut block_indexes = vec![];
+        let proof_merkle_root = proof
+            .extract_matches(&mut txids, &mut block_indexes)
+            .map_err(|_| Error::BitcoinMerkleBlockError)?;
+        if proof_merkle_root != header.merkle_root() {
+            return Err(Error::Header(
+                "Bitcoin merkle proof does not match header".into(),
+            ));
+        }
+        if txids.len() != 1 {
+            return Err(Error::Header(
+                "Bitcoin merkle proof contains an invalid number of txids".into(),
+            ));
+        }
+        if txids[0] != txid {
+            return Err(Error::Header(
+                "Bitcoin merkle proof does not match transaction".into(),
+            ));
+        }
+
+        Ok(())
+    }
+
     #[query]
     pub fn trusted_height(&self) -> u32 {
         self.config.trusted_height
@@ -606,17 +820,30 @@ impl HeaderQueue {
         let mut queue = Self {
             deque: State::create(store.sub(&[0]), data.0)?,
             current_work: State::create(store.sub(&[1]), data.1)?,
-            config: config.clone(),
+            config,
         };
+
+        queue.seed_trusted_header()?;
+
+        Ok(queue)
+    }
+
+    /// Pushes the configured trusted header if the queue is empty, e.g. when
+    /// the queue is created at genesis.
+    fn seed_trusted_header(&mut self) -> OrgaResult<()> {
+        if !self.is_empty() {
+            return Ok(());
+        }
+
         let decoded_adapter: Adapter<BlockHeader> =
-            Decode::decode(config.encoded_trusted_header.as_slice())?;
-        let wrapped_header = WrappedHeader::new(decoded_adapter, config.trusted_height);
+            Decode::decode(self.config.encoded_trusted_header.as_slice())?;
+        let wrapped_header = WrappedHeader::new(decoded_adapter, self.config.trusted_height);
         let work_header = WorkHeader::new(wrapped_header.clone(), wrapped_header.work());
 
-        queue.current_work = Adapter::new(wrapped_header.work());
-        queue.deque.push_front(work_header.into())?;
+        self.current_work = Adapter::new(work_header.work());
+        self.deque.push_front(work_header.into())?;
 
-        Ok(queue)
+        Ok(())
     }
 
     pub fn network(&self) -> bitcoin::Network {
@@ -627,23 +854,84 @@ impl HeaderQueue {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::bitcoin::test_utils::{self, mem_store, mine_block, mine_header_with_root};
+    use bitcoin::blockdata::constants::genesis_block;
     use bitcoin::hash_types::TxMerkleNode;
     use bitcoin::BlockHash;
     use bitcoin_hashes::hex::FromHex;
     use bitcoin_hashes::sha256d::Hash;
     use chrono::{TimeZone, Utc};
+    use orga::context::Context;
+    use orga::plugins::Paid;
+
+    fn regtest_queue() -> (HeaderQueue, WrappedHeader) {
+        test_utils::regtest_queue(mem_store())
+    }
+
+    fn mine_header(prev: &BlockHeader, spacing: u32) -> BlockHeader {
+        let prev_header = WrappedHeader::from_header(prev, 0);
+        mine_header_with_root(&prev_header, prev.merkle_root, spacing)
+    }
+
+    fn mine_invalid_header(prev: &BlockHeader, spacing: u32) -> BlockHeader {
+        let mut header = mine_header(prev, spacing);
+
+        while header.validate_pow(&header.target()).is_ok() {
+            header.nonce += 1;
+        }
+
+        header
+    }
+
+    fn mine_chain(prev: &WrappedHeader, count: u32, spacing: u32) -> Vec<WrappedHeader> {
+        let mut headers: Vec<WrappedHeader> = Vec::with_capacity(count as usize);
+        let mut prev = prev.clone();
+
+        for _ in 0..count {
+            let header = mine_header(&prev.header, spacing);
+            prev = WrappedHeader::from_header(&header, prev.height() + 1);
+            headers.push(prev.clone());
+        }
+
+        headers
+    }
 
     #[test]
     fn create() {
-        let store = Store::new(Shared::new(MapStore::new()).into());
+        let store = mem_store();
         let q = HeaderQueue::create(store, Default::default()).unwrap();
 
-        // let decoded_adapter: Adapter<BlockHeader> =
-        //     Decode::decode(ENCODED_TRUSTED_HEADER.as_slice()).unwrap();
-        // let wrapped_header = WrappedHeader::new(decoded_adapter, TRUSTED_HEIGHT);
+        let config = Config::mainnet();
+        let decoded_adapter: Adapter<BlockHeader> =
+            Decode::decode(config.encoded_trusted_header.as_slice()).unwrap();
+        let wrapped_header = WrappedHeader::new(decoded_adapter, config.trusted_height);
+
+        assert_eq!(q.height().unwrap(), wrapped_header.height());
+        assert_eq!(q.trusted_height(), q.height().unwrap());
+        assert_eq!(*q.current_work, wrapped_header.work());
+    }
 
-        // assert_eq!(q.height().unwrap(), wrapped_header.height());
-        // assert_eq!(*q.current_work, wrapped_header.work());
+    #[test]
+    fn with_conf_seeds_once() {
+        let genesis = genesis_block(bitcoin::Network::Regtest).header;
+        let config = Config::regtest(&genesis, 0);
+        let store = mem_store();
+
+        let mut q =
+            HeaderQueue::with_conf(store.clone(), Default::default(), config.clone()).unwrap();
+        assert_eq!(q.trusted_height(), q.height().unwrap());
+        assert_eq!(q.len(), 1);
+
+        let wrapped_genesis = WrappedHeader::from_header(&genesis, 0);
+        q.add_into_iter(mine_chain(&wrapped_genesis, 3, 10 * 60))
+            .unwrap();
+        let work = *q.current_work;
+        let data = State::flush(q).unwrap();
+
+        let q = HeaderQueue::with_conf(store, data, config).unwrap();
+        assert_eq!(q.height().unwrap(), 3);
+        assert_eq!(q.len(), 4);
+        assert_eq!(*q.current_work, work);
     }
 
     #[test]
@@ -807,6 +1095,7 @@ mod test {
         let test_config = Config {
             max_length: 2000,
             max_time_increase: 8 * 60 * 60,
+            max_future_drift: 2 * 60 * 60,
             trusted_height: 42,
             retarget_interval: 2016,
             target_spacing: 10 * 60,
@@ -823,7 +1112,7 @@ mod test {
             ],
             network: bitcoin::Network::Bitcoin,
         };
-        let store = Store::new(Shared::new(MapStore::new()).into());
+        let store = mem_store();
         let mut q = HeaderQueue::with_conf(store, Default::default(), test_config).unwrap();
         q.add(header_list.into()).unwrap();
     }
@@ -850,6 +1139,7 @@ mod test {
         let test_config = Config {
             max_length: 2000,
             max_time_increase: 8 * 60 * 60,
+            max_future_drift: 2 * 60 * 60,
             trusted_height: 42,
             retarget_interval: 2016,
             target_spacing: 10 * 60,
@@ -869,13 +1159,13 @@ mod test {
 
         let adapter = Adapter::new(header);
         let header_list = [WrappedHeader::new(adapter, 43)];
-        let store = Store::new(Shared::new(MapStore::new()).into());
+        let store = mem_store();
         let mut q = HeaderQueue::with_conf(store, Default::default(), test_config.clone()).unwrap();
         q.add_into_iter(header_list).unwrap();
 
         let adapter = Adapter::new(header);
         let header_list = vec![WrappedHeader::new(adapter, 43)];
-        let store = Store::new(Shared::new(MapStore::new()).into());
+        let store = mem_store();
         let mut q = HeaderQueue::with_conf(store, Default::default(), test_config).unwrap();
         q.add_into_iter(header_list).unwrap();
     }
@@ -903,6 +1193,55 @@ mod test {
         let test_config = Config {
             max_length: 2000,
             max_time_increase: 8 * 60 * 60,
+            max_future_drift: 2 * 60 * 60,
+            trusted_height: 42,
+            retarget_interval: 2016,
+            target_spacing: 10 * 60,
+            target_timespan: 2016 * (10 * 60),
+            max_target: 0x1d00ffff,
+            retargeting: true,
+            min_difficulty_blocks: false,
+            encoded_trusted_header: vec![
+                1, 0, 0, 0, 139, 82, 187, 215, 44, 47, 73, 86, 144, 89, 245, 89, 193, 177, 121, 77,
+                229, 25, 46, 79, 125, 109, 43, 3, 199, 72, 43, 173, 0, 0, 0, 0, 131, 228, 248, 169,
+                213, 2, 237, 12, 65, 144, 117, 193, 171, 181, 213, 111, 135, 138, 46, 144, 121,
+                229, 97, 43, 251, 118, 162, 220, 55, 217, 196, 39, 65, 221, 104, 73, 255, 255, 0,
+                29, 43, 144, 157, 214,
+            ],
+            network: bitcoin::Network::Bitcoin,
+        };
+
+        let adapter = Adapter::new(header);
+        let header_list = [WrappedHeader::new(adapter, 43)];
+        let store = mem_store();
+        let mut q = HeaderQueue::with_conf(store, Default::default(), test_config).unwrap();
+        q.add_into_iter(header_list).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "Header at height 43 failed PoW validation")]
+    fn add_bad_pow() {
+        let stamp = Utc.ymd(2009, 1, 10).and_hms(17, 44, 37);
+
+        let header = BlockHeader {
+            version: 0x1,
+            prev_blockhash: BlockHash::from_hash(
+                Hash::from_hex("00000000314e90489514c787d615cea50003af2023796ccdd085b6bcc1fa28f5")
+                    .unwrap(),
+            ),
+            merkle_root: TxMerkleNode::from_hash(
+                Hash::from_hex("2f5c03ce19e9a855ac93087a1b68fe6592bcf4bd7cbb9c1ef264d886a785894e")
+                    .unwrap(),
+            ),
+            time: stamp.timestamp() as u32,
+            bits: 486_604_799,
+            nonce: 2_093_702_201,
+        };
+
+        let test_config = Config {
+            max_length: 2000,
+            max_time_increase: 8 * 60 * 60,
+            max_future_drift: 2 * 60 * 60,
             trusted_height: 42,
             retarget_interval: 2016,
             target_spacing: 10 * 60,
@@ -922,8 +1261,430 @@ mod test {
 
         let adapter = Adapter::new(header);
         let header_list = [WrappedHeader::new(adapter, 43)];
-        let store = Store::new(Shared::new(MapStore::new()).into());
+        let store = mem_store();
         let mut q = HeaderQueue::with_conf(store, Default::default(), test_config).unwrap();
         q.add_into_iter(header_list).unwrap();
     }
+
+    #[test]
+    fn tip() {
+        let (mut q, genesis) = regtest_queue();
+        assert_eq!(q.tip().unwrap(), Some((0, genesis.block_hash())));
+
+        let headers = mine_chain(&genesis, 5, 10 * 60);
+        let last = headers.last().unwrap().clone();
+        q.add_into_iter(headers).unwrap();
+
+        assert_eq!(q.tip().unwrap(), Some((5, last.block_hash())));
+    }
+
+    #[test]
+    fn tip_empty() {
+        let store = mem_store();
+        let q = HeaderQueue {
+            deque: State::create(store.sub(&[0]), Default::default()).unwrap(),
+            current_work: Default::default(),
+            config: Config::mainnet(),
+        };
+
+        assert_eq!(q.tip().unwrap(), None);
+    }
+
+    #[test]
+    fn future_time() {
+        let (mut q, genesis) = regtest_queue();
+        let headers = mine_chain(&genesis, 10, 10 * 60);
+        let tip = headers.last().unwrap().clone();
+        q.add_into_iter(headers).unwrap();
+
+        Context::add(Paid::default());
+        Context::add(Time::from_seconds(tip.time() as i64));
+        let max_time = tip.time() + q.config.max_future_drift;
+
+        let too_late = mine_header(&tip.header, max_time + 1 - tip.time());
+        let err = q
+            .add(vec![WrappedHeader::from_header(&too_late, 11)].into())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            OrgaError::App("Header timestamp is too far in the future".to_string()).to_string()
+        );
+
+        let on_time = mine_header(&tip.header, max_time - tip.time());
+        q.add(vec![WrappedHeader::from_header(&on_time, 11)].into())
+            .unwrap();
+        assert_eq!(q.height().unwrap(), 11);
+    }
+
+    #[test]
+    fn far_ahead_of_median_time_past() {
+        // real mainnet headers can be more than a day ahead of median time
+        // past (e.g. heights 11966-11969), which must not be rejected without
+        // a network time to compare against
+        let (mut q, genesis) = regtest_queue();
+        let headers = mine_chain(&genesis, 10, 10 * 60);
+        let tip = headers.last().unwrap().clone();
+        q.add_into_iter(headers).unwrap();
+
+        let header = mine_header(&tip.header, 36 * 60 * 60);
+        q.add_into_iter([WrappedHeader::from_header(&header, 11)])
+            .unwrap();
+        assert_eq!(q.height().unwrap(), 11);
+    }
+
+    fn mock_txids(values: &[u64]) -> Vec<Txid> {
+        values
+            .iter()
+            .map(|value| {
+                bitcoin::Transaction {
+                    version: 1,
+                    lock_time: 0,
+                    input: vec![],
+                    output: vec![bitcoin::TxOut {
+                        value: *value,
+                        script_pubkey: Default::default(),
+                    }],
+                }
+                .txid()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn verify_tx_proof() {
+        let (mut q, genesis) = regtest_queue();
+
+        let txids = mock_txids(&[1, 2, 3]);
+        let block = mine_block(&mut q, &genesis, &txids);
+        let other_txids = mock_txids(&[4, 5]);
+        mine_block(&mut q, &block, &other_txids);
+
+        let proof = PartialMerkleTree::from_txids(&txids, &[false, true, false]);
+        q.verify_tx_proof(1, &proof, txids[1]).unwrap();
+
+        let err = q.verify_tx_proof(1, &proof, txids[0]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Bitcoin merkle proof does not match transaction"
+        );
+
+        let other_proof = PartialMerkleTree::from_txids(&other_txids, &[true, false]);
+        let err = q
+            .verify_tx_proof(1, &other_proof, other_txids[0])
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Bitcoin merkle proof does not match header"
+        );
+
+        let err = q.verify_tx_proof(3, &proof, txids[1]).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid bitcoin block height");
+    }
+
+    #[test]
+    fn equal_work_reorg() {
+        let (mut q, genesis) = regtest_queue();
+        let headers = mine_chain(&genesis, 3, 10 * 60);
+        let fork_point = headers[0].clone();
+        let tip = headers.last().unwrap().clone();
+        q.add_into_iter(headers).unwrap();
+
+        let fork = mine_chain(&fork_point, 2, 11 * 60);
+        let err = q.add_into_iter(fork).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "New best chain must include more work than old best chain."
+        );
+
+        assert_eq!(q.tip().unwrap(), Some((3, tip.block_hash())));
+    }
+
+    #[test]
+    fn import_trusted_batch() {
+        let genesis = genesis_block(bitcoin::Network::Regtest).header;
+        let store = mem_store();
+        let q = HeaderQueue::with_conf(
+            store.clone(),
+            Default::default(),
+            Config::regtest(&genesis, 0),
+        )
+        .unwrap();
+        let data = State::flush(q).unwrap();
+
+        let mut headers = Vec::with_capacity(6);
+        let mut prev = WrappedHeader::from_header(&genesis, 0);
+        for _ in 0..6 {
+            let header = mine_invalid_header(&prev.header, 10 * 60);
+            prev = WrappedHeader::from_header(&header, prev.height() + 1);
+            headers.push(prev.clone());
+        }
+        let checkpoint = headers[4].clone();
+
+        // a newer trusted header than the one the queue was created from
+        let mut config = Config::regtest(&checkpoint.header, 5);
+        config.max_length = 4;
+        let mut q = HeaderQueue::with_conf(store, data, config).unwrap();
+        assert_eq!(q.height().unwrap(), 0);
+
+        let err = q.import_trusted_batch(headers.clone(), 6).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Trusted batch height is above the configured trusted height"
+        );
+
+        let err = q.import_trusted_batch(headers.clone(), 5).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Trusted header is above the trusted batch height"
+        );
+        assert_eq!(q.height().unwrap(), 0);
+
+        let err = q
+            .import_trusted_batch(headers[..4].to_vec(), 5)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Trusted batch must end at the configured trusted height"
+        );
+        assert_eq!(q.height().unwrap(), 0);
+
+        let mut forged = headers[..5].to_vec();
+        let other = mine_invalid_header(&headers[3].header, 11 * 60);
+        forged[4] = WrappedHeader::from_header(&other, 5);
+        let err = q.import_trusted_batch(forged, 5).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Trusted batch does not match the configured trusted header"
+        );
+        assert_eq!(q.height().unwrap(), 0);
+
+        q.import_trusted_batch(headers[..5].to_vec(), 5).unwrap();
+        assert_eq!(q.tip().unwrap(), Some((5, checkpoint.block_hash())));
+        assert_eq!(q.len(), 4);
+
+        let invalid = mine_invalid_header(&checkpoint.header, 10 * 60);
+        assert!(q
+            .add_into_iter([WrappedHeader::from_header(&invalid, 6)])
+            .is_err());
+
+        let valid = mine_header(&checkpoint.header, 10 * 60);
+        q.add_into_iter([WrappedHeader::from_header(&valid, 6)])
+            .unwrap();
+        assert_eq!(q.height().unwrap(), 6);
+    }
+
+    #[test]
+    fn add_overlapping_batch() {
+        let (mut q, genesis) = regtest_queue();
+        let headers = mine_chain(&genesis, 6, 10 * 60);
+        q.add_into_iter(headers[..4].to_vec()).unwrap();
+
+        q.add_into_iter(headers[..4].to_vec()).unwrap();
+        assert_eq!(q.height().unwrap(), 4);
+        assert_eq!(q.len(), 5);
+
+        q.add_into_iter(headers[2..].to_vec()).unwrap();
+        assert_eq!(q.len(), 7);
+        assert_eq!(
+            q.tip().unwrap(),
+            Some((6, headers.last().unwrap().block_hash()))
+        );
+
+        // a conflicting header after the known ones is treated as a reorg
+        let fork = mine_chain(&headers[3], 1, 11 * 60);
+        let batch = vec![headers[3].clone(), fork[0].clone()];
+        let err = q.add_into_iter(batch).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "New best chain must include more work than old best chain."
+        );
+        assert_eq!(q.height().unwrap(), 6);
+    }
+
+    #[test]
+    fn add_relayed_known_batch() {
+        let (mut q, genesis) = regtest_queue();
+        let headers = mine_chain(&genesis, 6, 10 * 60);
+        q.add_relayed(headers[..4].to_vec(), None).unwrap();
+
+        let err = q.add_relayed(headers[..4].to_vec(), None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Passed header list contains no new headers"
+        );
+        assert_eq!(q.height().unwrap(), 4);
+
+        // the known prefix is still skipped when new headers follow it
+        q.add_relayed(headers[2..].to_vec(), None).unwrap();
+        assert_eq!(q.height().unwrap(), 6);
+    }
+
+    #[test]
+    fn add_contiguous_batch() {
+        let (mut q, genesis) = regtest_queue();
+        let headers = mine_chain(&genesis, 10, 10 * 60);
+        let last = headers.last().unwrap().clone();
+
+        q.add_into_iter(headers).unwrap();
+
+        assert_eq!(q.height().unwrap(), 10);
+        assert_eq!(q.tip().unwrap(), Some((10, last.block_hash())));
+        assert_eq!(
+            q.get_by_height(5).unwrap().unwrap().block_hash(),
+            q.get_by_height(6).unwrap().unwrap().header.prev_blockhash()
+        );
+    }
+
+    #[test]
+    fn add_height_gap() {
+        let (mut q, genesis) = regtest_queue();
+        let headers = mine_chain(&genesis, 4, 10 * 60);
+
+        let err = q.add_into_iter(headers[1..].to_vec()).unwrap_err();
+        assert_eq!(err.to_string(), "Headers not connect to chain");
+
+        let batch = vec![headers[0].clone(), headers[1].clone(), headers[3].clone()];
+        let err = q.add_into_iter(batch).unwrap_err();
+        assert_eq!(err.to_string(), "Non-consecutive headers passed");
+        assert_eq!(q.height().unwrap(), 0);
+    }
+
+    fn retarget_queue(retargeting: bool, spacing: u32) -> (HeaderQueue, WrappedHeader) {
+        let genesis = genesis_block(bitcoin::Network::Regtest).header;
+        let mut config = Config::regtest(&genesis, 4);
+        config.retarget_interval = 4;
+        config.target_timespan = 4 * config.target_spacing;
+        config.retargeting = retargeting;
+
+        let store = mem_store();
+        let mut q = HeaderQueue::with_conf(store, Default::default(), config).unwrap();
+
+        let trusted = WrappedHeader::from_header(&genesis, 4);
+        let headers = mine_chain(&trusted, 3, spacing);
+        let tip = headers.last().unwrap().clone();
+        q.add_into_iter(headers).unwrap();
+
+        (q, tip)
+    }
+
+    fn mine_with_bits(prev: &WrappedHeader, bits: u32) -> WrappedHeader {
+        let mut header = mine_header(&prev.header, 10 * 60);
+        header.bits = bits;
+        header.nonce = 0;
+        while header.validate_pow(&header.target()).is_err() {
+            header.nonce += 1;
+        }
+
+        WrappedHeader::from_header(&header, prev.height() + 1)
+    }
+
+    fn scaled_bits(bits: u32, numerator: u32, denominator: u32) -> u32 {
+        let target = WrappedHeader::u256_from_compact(bits) * WrappedHeader::u32_to_u256(numerator)
+            / WrappedHeader::u32_to_u256(denominator);
+        BlockHeader::compact_target_from_u256(&target)
+    }
+
+    #[test]
+    fn retarget() {
+        // the last period took 3 spacings of 10 minutes out of a 40 minute
+        // target timespan, so the target shrinks to 3/4
+        let (mut q, tip) = retarget_queue(true, 10 * 60);
+        let expected_bits = scaled_bits(tip.bits(), 30 * 60, 40 * 60);
+
+        let err = q
+            .add_into_iter([mine_with_bits(&tip, tip.bits())])
+            .unwrap_err();
+        assert_eq!(err.to_string(), "block target incorrect");
+
+        q.add_into_iter([mine_with_bits(&tip, expected_bits)])
+            .unwrap();
+        assert_eq!(q.height().unwrap(), 8);
+    }
+
+    #[test]
+    fn retarget_clamped() {
+        // blocks came much faster than the target spacing, the adjustment is
+        // clamped to a factor of 4
+        let (mut q, tip) = retarget_queue(true, 1);
+        let expected_bits = scaled_bits(tip.bits(), 1, 4);
+
+        q.add_into_iter([mine_with_bits(&tip, expected_bits)])
+            .unwrap();
+        assert_eq!(q.height().unwrap(), 8);
+    }
+
+    #[test]
+    fn retargeting_disabled() {
+        let (mut q, tip) = retarget_queue(false, 10 * 60);
+        let adjusted_bits = scaled_bits(tip.bits(), 30 * 60, 40 * 60);
+
+        assert!(q
+            .add_into_iter([mine_with_bits(&tip, adjusted_bits)])
+            .is_err());
+
+        q.add_into_iter([mine_with_bits(&tip, tip.bits())]).unwrap();
+        assert_eq!(q.height().unwrap(), 8);
+    }
+
+    #[test]
+    fn reorg_to_heavier_fork() {
+        let (mut q, genesis) = regtest_queue();
+        let headers = mine_chain(&genesis, 4, 10 * 60);
+        q.add_into_iter(headers.clone()).unwrap();
+
+        let fork = mine_chain(&headers[1], 3, 11 * 60);
+        let fork_tip = fork.last().unwrap().clone();
+        q.add_into_iter(fork.clone()).unwrap();
+
+        assert_eq!(q.tip().unwrap(), Some((5, fork_tip.block_hash())));
+        assert_eq!(
+            q.get_by_height(3).unwrap().unwrap().block_hash(),
+            fork[0].block_hash()
+        );
+
+        // chain work matches a queue which only ever saw the fork
+        let (mut expected, _) = regtest_queue();
+        expected
+            .add_into_iter(headers[..2].iter().chain(fork.iter()).cloned())
+            .unwrap();
+        assert_eq!(*q.current_work, *expected.current_work);
+    }
+
+    #[test]
+    fn reorg_to_invalid_fork() {
+        let (mut q, genesis) = regtest_queue();
+        let headers = mine_chain(&genesis, 4, 10 * 60);
+        let tip = headers.last().unwrap().clone();
+        q.add_into_iter(headers.clone()).unwrap();
+        let work = *q.current_work;
+
+        // claims more work than the replaced headers, but the last header
+        // fails proof-of-work
+        let mut fork = mine_chain(&headers[1], 2, 11 * 60);
+        let invalid = mine_invalid_header(&fork[1].header, 10 * 60);
+        fork.push(WrappedHeader::from_header(&invalid, 5));
+
+        let err = q.add_into_iter(fork).unwrap_err();
+        assert_eq!(err.to_string(), "Header at height 5 failed PoW validation");
+
+        assert_eq!(q.tip().unwrap(), Some((4, tip.block_hash())));
+        assert_eq!(
+            q.get_by_height(3).unwrap().unwrap().block_hash(),
+            headers[2].block_hash()
+        );
+        assert_eq!(*q.current_work, work);
+    }
+
+    #[test]
+    fn add_batch_with_invalid_header() {
+        let (mut q, genesis) = regtest_queue();
+        let mut headers = mine_chain(&genesis, 3, 10 * 60);
+        let invalid = mine_invalid_header(&headers[2].header, 10 * 60);
+        headers.push(WrappedHeader::from_header(&invalid, 4));
+
+        let err = q.add_into_iter(headers).unwrap_err();
+        assert_eq!(err.to_string(), "Header at height 4 failed PoW validation");
+        assert_eq!(q.tip().unwrap(), Some((0, genesis.block_hash())));
+        assert_eq!(*q.current_work, genesis.work());
+    }
 }
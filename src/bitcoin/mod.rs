@@ -6,7 +6,7 @@ use bitcoin::hashes::Hash;
 use bitcoin::util::bip32::ExtendedPubKey;
 use bitcoin::Script;
 use bitcoin::{util::merkleblock::PartialMerkleTree, Transaction};
-use checkpoint::{CheckpointQueue, FEE_RATE};
+use checkpoint::{est_input_vsize, CheckpointQueue, FEE_RATE};
 use header_queue::HeaderQueue;
 #[cfg(feature = "full")]
 use orga::abci::BeginBlock;
@@ -24,6 +24,7 @@ use orga::query::Query;
 use orga::state::State;
 use orga::{Error as OrgaError, Result as OrgaResult};
 use signatory::SignatorySet;
+use threshold_sig::{LengthVec, Pubkey};
 use txid_set::OutpointSet;
 
 pub mod adapter;
@@ -34,6 +35,8 @@ pub mod relayer;
 pub mod signatory;
 #[cfg(feature = "full")]
 pub mod signer;
+#[cfg(test)]
+pub(crate) mod test_utils;
 pub mod threshold_sig;
 pub mod txid_set;
 
@@ -47,12 +50,78 @@ pub const MIN_DEPOSIT_AMOUNT: u64 = 600;
 pub const MAX_WITHDRAWAL_SCRIPT_LENGTH: u64 = 64;
 pub const TRANSFER_FEE: u64 = 1 * UNITS_PER_SAT;
 pub const MIN_CONFIRMATIONS: u32 = 3;
+pub const MAX_DEPOSIT_BATCH: usize = 25;
 pub const UNITS_PER_SAT: u64 = 1_000_000;
+pub const WITHDRAWAL_FEE: u64 = 1_000 * UNITS_PER_SAT;
+pub const DUST_THRESHOLD: u64 = 546;
 
 pub fn calc_deposit_fee(amount: u64) -> u64 {
     amount / 5
 }
 
+/// Parameters of the peg which are stored in state. They are set at genesis
+/// in `InitChain`.
+#[derive(Encode, Decode, Clone, Debug)]
+pub struct Config {
+    /// Fee in nBTC units deducted from each withdrawal and paid to the reward
+    /// pool.
+    pub withdrawal_fee: u64,
+    /// Minimum value in satoshis of a withdrawal output.
+    pub dust_threshold: u64,
+    /// Maximum number of signatories in a signatory set.
+    pub max_signatories: u64,
+    /// Minimum voting power for a validator to be included in a signatory
+    /// set.
+    pub min_signatory_voting_power: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            withdrawal_fee: WITHDRAWAL_FEE,
+            dust_threshold: DUST_THRESHOLD,
+            max_signatories: signatory::MAX_SIGNATORIES,
+            min_signatory_voting_power: signatory::MIN_SIGNATORY_VOTING_POWER,
+        }
+    }
+}
+
+impl State for Config {
+    type Encoding = Self;
+
+    fn create(_: orga::store::Store, data: Self) -> OrgaResult<Self> {
+        Ok(data)
+    }
+
+    fn flush(self) -> OrgaResult<Self> {
+        Ok(self)
+    }
+}
+
+impl Terminated for Config {}
+
+/// Returns the value in satoshis paid out to the withdrawal output, after
+/// deducting the withdrawal fee and the output's share of the miner fee.
+/// `amount` is in nBTC units.
+pub fn calc_withdrawal_value(config: &Config, amount: u64, script_len: u64) -> Result<u64> {
+    let amount = amount.checked_sub(config.withdrawal_fee).ok_or_else(|| {
+        OrgaError::App("Withdrawal is too small to pay its withdrawal fee".to_string())
+    })?;
+
+    let miner_fee = (9 + script_len) * FEE_RATE;
+    let value = (amount / UNITS_PER_SAT)
+        .checked_sub(miner_fee)
+        .ok_or_else(|| {
+            OrgaError::App("Withdrawal is too small to pay its miner fee".to_string())
+        })?;
+
+    if value < config.dust_threshold {
+        return Err(OrgaError::App("Withdrawal amount is below dust threshold".to_string()).into());
+    }
+
+    Ok(value)
+}
+
 #[derive(State, Call, Query, Client)]
 pub struct Bitcoin {
     pub headers: HeaderQueue,
@@ -61,8 +130,21 @@ pub struct Bitcoin {
     pub accounts: Accounts<Nbtc>,
     pub signatory_keys: SignatoryKeys,
     pub(crate) reward_pool: Coin<Nbtc>,
+    pub config: Config,
 }
 
+#[derive(Encode, Decode)]
+pub struct Deposit {
+    pub btc_tx: Adapter<Transaction>,
+    pub btc_height: u32,
+    pub btc_proof: Adapter<PartialMerkleTree>,
+    pub btc_vout: u32,
+    pub sigset_index: u32,
+    pub dest: Address,
+}
+
+impl Terminated for Deposit {}
+
 pub type ConsensusKey = [u8; 32];
 
 #[derive(Call, Query, Client, Clone)]
@@ -187,44 +269,89 @@ impl Bitcoin {
     ) -> Result<()> {
         exempt_from_fee()?;
 
-        if dest.is_null() {
-            return Err(OrgaError::App("Cannot deposit to null address".to_string()).into());
+        let deposit = Deposit {
+            btc_tx,
+            btc_height,
+            btc_proof,
+            btc_vout,
+            sigset_index,
+            dest,
+        };
+        let sigset = self.verify_deposit(&deposit)?;
+        self.credit_deposit(&deposit, &sigset)
+    }
+
+    /// Relays a batch of deposits in one call. If `skip_invalid` is set,
+    /// deposits which fail verification are ignored, otherwise any invalid
+    /// deposit fails the whole batch.
+    #[call]
+    pub fn relay_deposits(
+        &mut self,
+        deposits: LengthVec<u8, Deposit>,
+        skip_invalid: bool,
+    ) -> Result<()> {
+        exempt_from_fee()?;
+
+        self.relay_deposit_batch(deposits.into(), skip_invalid)
+    }
+
+    fn relay_deposit_batch(&mut self, deposits: Vec<Deposit>, skip_invalid: bool) -> Result<()> {
+        if deposits.is_empty() {
+            return Err(OrgaError::App("Passed deposit list empty".to_string()).into());
+        }
+
+        if deposits.len() > MAX_DEPOSIT_BATCH {
+            return Err(
+                OrgaError::App("Exceeded maximum amount of relayed deposits".to_string()).into(),
+            );
         }
 
-        let btc_header = self
-            .headers
-            .get_by_height(btc_height)?
-            .ok_or_else(|| OrgaError::App("Invalid bitcoin block height".to_string()))?;
+        let mut credited = 0;
+        for deposit in deposits.iter() {
+            let sigset = match self.verify_deposit(deposit) {
+                Ok(sigset) => sigset,
+                Err(_) if skip_invalid => continue,
+                Err(err) => return Err(err),
+            };
 
-        if self.headers.height()? - btc_height < MIN_CONFIRMATIONS {
-            return Err(OrgaError::App("Block is not sufficiently confirmed".to_string()).into());
+            self.credit_deposit(deposit, &sigset)?;
+            credited += 1;
         }
 
-        let mut txids = vec![];
-        let mut block_indexes = vec![];
-        let proof_merkle_root = btc_proof
-            .extract_matches(&mut txids, &mut block_indexes)
-            .map_err(|_| Error::BitcoinMerkleBlockError)?;
-        if proof_merkle_root != btc_header.merkle_root() {
-            return Err(OrgaError::App(
-                "Bitcoin merkle proof does not match header".to_string(),
-            ))?;
+        // relaying is exempt from fees, so don't accept a batch which does
+        // nothing as a free transaction
+        if credited == 0 {
+            return Err(OrgaError::App("No deposits in batch were credited".to_string()).into());
         }
-        if txids.len() != 1 {
-            return Err(OrgaError::App(
-                "Bitcoin merkle proof contains an invalid number of txids".to_string(),
-            ))?;
+
+        Ok(())
+    }
+
+    fn verify_deposit(&self, deposit: &Deposit) -> Result<SignatorySet> {
+        let Deposit {
+            btc_tx,
+            btc_height,
+            btc_proof,
+            btc_vout,
+            sigset_index,
+            dest,
+        } = deposit;
+
+        if dest.is_null() {
+            return Err(OrgaError::App("Cannot deposit to null address".to_string()).into());
         }
-        if txids[0] != btc_tx.txid() {
-            return Err(OrgaError::App(
-                "Bitcoin merkle proof does not match transaction".to_string(),
-            ))?;
+
+        self.headers
+            .verify_tx_proof(*btc_height, btc_proof, btc_tx.txid())?;
+
+        if self.headers.height()? - btc_height < MIN_CONFIRMATIONS {
+            return Err(OrgaError::App("Block is not sufficiently confirmed".to_string()).into());
         }
 
-        if btc_vout as usize >= btc_tx.output.len() {
+        if *btc_vout as usize >= btc_tx.output.len() {
             return Err(OrgaError::App("Output index is out of bounds".to_string()))?;
         }
-        let output = &btc_tx.output[btc_vout as usize];
+        let output = &btc_tx.output[*btc_vout as usize];
 
         if output.value < MIN_DEPOSIT_AMOUNT {
             return Err(OrgaError::App(
@@ -232,7 +359,7 @@ impl Bitcoin {
             ))?;
         }
 
-        let sigset = self.checkpoints.get(sigset_index)?.sigset.clone();
+        let sigset = self.checkpoints.get(*sigset_index)?.sigset.clone();
 
         let now = self
             .context::<Time>()
@@ -242,44 +369,57 @@ impl Bitcoin {
             return Err(OrgaError::App("Deposit timeout has expired".to_string()))?;
         }
 
-        let expected_script = sigset.output_script(dest)?;
+        let expected_script = sigset.output_script(*dest)?;
         if output.script_pubkey != expected_script {
             return Err(OrgaError::App(
                 "Output script does not match signature set".to_string(),
             ))?;
         }
 
-        let outpoint = (btc_tx.txid().into_inner(), btc_vout);
+        let outpoint = (btc_tx.txid().into_inner(), *btc_vout);
         if self.processed_outpoints.contains(outpoint)? {
             return Err(OrgaError::App(
                 "Output has already been relayed".to_string(),
             ))?;
         }
+
+        let est_vsize = est_input_vsize(sigset.est_witness_vsize());
+        if output.value < est_vsize * FEE_RATE {
+            return Err(OrgaError::App(
+                "Deposit amount is too small to pay its spending fee".to_string(),
+            ))?;
+        }
+
+        Ok(sigset)
+    }
+
+    fn credit_deposit(&mut self, deposit: &Deposit, sigset: &SignatorySet) -> Result<()> {
+        let btc_tx = &deposit.btc_tx;
+        let output = &btc_tx.output[deposit.btc_vout as usize];
+
+        let outpoint = (btc_tx.txid().into_inner(), deposit.btc_vout);
         self.processed_outpoints
             .insert(outpoint, sigset.deposit_timeout())?;
 
         let prevout = bitcoin::OutPoint {
             txid: btc_tx.txid(),
-            vout: btc_vout,
+            vout: deposit.btc_vout,
         };
-        let est_vsize =
-            self.checkpoints
-                .building_mut()?
-                .push_input(prevout, &sigset, dest, output.value)?;
+        let est_vsize = self.checkpoints.building_mut()?.push_input(
+            prevout,
+            sigset,
+            deposit.dest,
+            output.value,
+        )?;
 
         // TODO: don't credit account until we're done signing including tx;
 
-        let value = output
-            .value
-            .checked_sub(est_vsize * FEE_RATE)
-            .ok_or_else(|| {
-                OrgaError::App("Deposit amount is too small to pay its spending fee".to_string())
-            })?
-            * UNITS_PER_SAT;
+        // verify_deposit already checked that the output pays its spending fee
+        let value = (output.value - est_vsize * FEE_RATE) * UNITS_PER_SAT;
 
         let mut minted_nbtc = Nbtc::mint(value);
         let deposit_fee = minted_nbtc.take(calc_deposit_fee(value))?;
-        self.accounts.deposit(dest, minted_nbtc)?;
+        self.accounts.deposit(deposit.dest, minted_nbtc)?;
         self.reward_pool.give(deposit_fee)?;
 
         Ok(())
@@ -299,22 +439,24 @@ impl Bitcoin {
             .signer
             .ok_or_else(|| Error::Orga(OrgaError::App("Call must be signed".into())))?;
 
-        self.accounts.withdraw(signer, amount)?.burn();
+        self.queue_withdrawal(signer, script_pubkey.into_inner(), amount)
+    }
 
-        let fee = (9 + script_pubkey.len() as u64) * FEE_RATE;
-        let value: u64 = Into::<u64>::into(amount) / UNITS_PER_SAT;
-        let value = match value.checked_sub(fee) {
-            None => {
-                return Err(OrgaError::App(
-                    "Withdrawal is too small to pay its miner fee".to_string(),
-                )
-                .into())
-            }
-            Some(value) => value,
-        };
+    fn queue_withdrawal(
+        &mut self,
+        signer: Address,
+        script_pubkey: Script,
+        amount: Amount,
+    ) -> Result<()> {
+        let value = calc_withdrawal_value(&self.config, amount.into(), script_pubkey.len() as u64)?;
+
+        let mut coins = self.accounts.withdraw(signer, amount)?;
+        let withdrawal_fee = coins.take(self.config.withdrawal_fee)?;
+        self.reward_pool.give(withdrawal_fee)?;
+        coins.burn();
 
         let output = bitcoin::TxOut {
-            script_pubkey: script_pubkey.into_inner(),
+            script_pubkey,
             value,
         };
 
@@ -346,6 +488,15 @@ impl Bitcoin {
         self.checkpoints.building()?.get_tvl()
     }
 
+    #[query]
+    pub fn signatory_set(&self) -> Result<Vec<(Pubkey, u64)>> {
+        let sigset = self.checkpoints.active_sigset()?;
+        Ok(sigset
+            .iter()
+            .map(|signatory| (signatory.pubkey, signatory.voting_power))
+            .collect())
+    }
+
     pub fn network(&self) -> bitcoin::Network {
         self.headers.network()
     }
@@ -355,8 +506,7 @@ impl Bitcoin {
 impl BeginBlock for Bitcoin {
     fn begin_block(&mut self, _ctx: &BeginBlockCtx) -> OrgaResult<()> {
         self.checkpoints
-            .maybe_step(self.signatory_keys.map())
-            .map_err(|err| OrgaError::App(err.to_string()))?;
+            .maybe_step(self.signatory_keys.map(), &self.config)?;
 
         Ok(())
     }
@@ -393,3 +543,284 @@ impl SignatoryKeys {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use header_queue::WrappedHeader;
+    use test_utils::{mem_store, mine_block, regtest_queue};
+
+    const P2WPKH_SCRIPT_LEN: u64 = 22;
+
+    fn regtest_bitcoin(sigset: SignatorySet) -> (Bitcoin, WrappedHeader) {
+        Context::add(Time::from_seconds(0));
+
+        let store = mem_store();
+        let mut btc = Bitcoin::create(store.sub(&[0]), Default::default()).unwrap();
+        let (headers, genesis) = regtest_queue(store.sub(&[1]));
+        btc.headers = headers;
+        btc.checkpoints.push_mock(sigset).unwrap();
+
+        (btc, genesis)
+    }
+
+    fn add_block(btc: &mut Bitcoin, prev: &WrappedHeader, txs: &[Transaction]) -> WrappedHeader {
+        let txids: Vec<_> = txs.iter().map(Transaction::txid).collect();
+        mine_block(&mut btc.headers, prev, &txids)
+    }
+
+    fn confirm(btc: &mut Bitcoin, prev: &WrappedHeader) -> WrappedHeader {
+        let coinbase = Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![],
+            output: vec![],
+        };
+
+        let mut prev = prev.clone();
+        for _ in 0..MIN_CONFIRMATIONS {
+            prev = add_block(btc, &prev, &[coinbase.clone()]);
+        }
+
+        prev
+    }
+
+    fn deposit_tx(sigset: &SignatorySet, dest: Address, value: u64) -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![],
+            output: vec![bitcoin::TxOut {
+                value,
+                script_pubkey: sigset.output_script(dest).unwrap(),
+            }],
+        }
+    }
+
+    fn mock_deposit(txs: &[Transaction], index: usize, btc_height: u32, dest: Address) -> Deposit {
+        let txids: Vec<_> = txs.iter().map(Transaction::txid).collect();
+        let mut matches = vec![false; txids.len()];
+        matches[index] = true;
+
+        Deposit {
+            btc_tx: Adapter::new(txs[index].clone()),
+            btc_height,
+            btc_proof: Adapter::new(PartialMerkleTree::from_txids(&txids, &matches)),
+            btc_vout: 0,
+            sigset_index: 0,
+            dest,
+        }
+    }
+
+    fn is_processed(btc: &Bitcoin, tx: &Transaction) -> bool {
+        btc.processed_outpoints
+            .contains((tx.txid().into_inner(), 0))
+            .unwrap()
+    }
+
+    #[test]
+    fn signatory_set_query() {
+        let (btc, _) = regtest_bitcoin(SignatorySet::mock(&[10, 30, 20]));
+
+        // read the result back through its encoding, as a query client would
+        let bytes = btc.signatory_set().unwrap().encode().unwrap();
+        let signatories: Vec<(Pubkey, u64)> = Decode::decode(bytes.as_slice()).unwrap();
+
+        let voting_powers: Vec<_> = signatories.iter().map(|(_, vp)| *vp).collect();
+        assert_eq!(voting_powers, vec![10, 30, 20]);
+        for (i, (pubkey, _)) in signatories.iter().enumerate() {
+            assert_eq!(pubkey.as_slice()[1], i as u8);
+        }
+    }
+
+    fn app_err(msg: &str) -> String {
+        OrgaError::App(msg.to_string()).to_string()
+    }
+
+    #[test]
+    fn verify_deposit() {
+        let sigset = SignatorySet::mock(&[10, 30, 20]);
+        let (mut btc, genesis) = regtest_bitcoin(sigset.clone());
+        let dest = Address::from_pubkey([2; 33]);
+
+        let txs = vec![
+            deposit_tx(&sigset, dest, 100_000),
+            deposit_tx(&sigset, dest, 200_000),
+        ];
+        let block = add_block(&mut btc, &genesis, &txs);
+        let other_txs = vec![deposit_tx(&sigset, dest, 300_000)];
+        let tip = add_block(&mut btc, &block, &other_txs);
+
+        let deposit = mock_deposit(&txs, 1, 1, dest);
+        let err = btc.verify_deposit(&deposit).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            app_err("Block is not sufficiently confirmed")
+        );
+
+        confirm(&mut btc, &tip);
+        let verified = btc.verify_deposit(&deposit).unwrap();
+        assert_eq!(verified.index(), 0);
+
+        // proof from a different block
+        let deposit = mock_deposit(&other_txs, 0, 1, dest);
+        let err = btc.verify_deposit(&deposit).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Bitcoin merkle proof does not match header"
+        );
+
+        let mut deposit = mock_deposit(&txs, 1, 1, dest);
+        deposit.btc_vout = 1;
+        let err = btc.verify_deposit(&deposit).unwrap_err();
+        assert_eq!(err.to_string(), app_err("Output index is out of bounds"));
+
+        let deposit = mock_deposit(&txs, 1, 1, Address::from_pubkey([3; 33]));
+        let err = btc.verify_deposit(&deposit).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            app_err("Output script does not match signature set")
+        );
+
+        let deposit = mock_deposit(&txs, 1, 1, dest);
+        btc.credit_deposit(&deposit, &sigset).unwrap();
+        let err = btc.verify_deposit(&deposit).unwrap_err();
+        assert_eq!(err.to_string(), app_err("Output has already been relayed"));
+    }
+
+    #[test]
+    fn relay_deposit_batch() {
+        for skip_invalid in [true, false] {
+            let sigset = SignatorySet::mock(&[10, 30, 20]);
+            let (mut btc, genesis) = regtest_bitcoin(sigset.clone());
+            let dest = Address::from_pubkey([2; 33]);
+
+            let txs = vec![
+                deposit_tx(&sigset, dest, 100_000),
+                deposit_tx(&sigset, dest, 200_000),
+            ];
+            let block = add_block(&mut btc, &genesis, &txs);
+            confirm(&mut btc, &block);
+
+            let valid = mock_deposit(&txs, 0, 1, dest);
+            // proves the first transaction while claiming the second
+            let mut invalid = mock_deposit(&txs, 0, 1, dest);
+            invalid.btc_tx = Adapter::new(txs[1].clone());
+
+            let res = btc.relay_deposit_batch(vec![valid, invalid], skip_invalid);
+            if skip_invalid {
+                res.unwrap();
+                assert!(is_processed(&btc, &txs[0]));
+                assert!(!is_processed(&btc, &txs[1]));
+                assert_eq!(btc.checkpoints.building().unwrap().inputs.len(), 1);
+            } else {
+                assert_eq!(
+                    res.unwrap_err().to_string(),
+                    "Bitcoin merkle proof does not match transaction"
+                );
+                assert!(!is_processed(&btc, &txs[1]));
+            }
+        }
+    }
+
+    #[test]
+    fn relay_deposit_batch_nothing_credited() {
+        let sigset = SignatorySet::mock(&[10, 30, 20]);
+        let (mut btc, genesis) = regtest_bitcoin(sigset.clone());
+        let dest = Address::from_pubkey([2; 33]);
+
+        let err = btc.relay_deposit_batch(vec![], true).unwrap_err();
+        assert_eq!(err.to_string(), app_err("Passed deposit list empty"));
+
+        let txs = vec![deposit_tx(&sigset, dest, 100_000)];
+        let block = add_block(&mut btc, &genesis, &txs);
+        confirm(&mut btc, &block);
+
+        let mut invalid = mock_deposit(&txs, 0, 1, dest);
+        invalid.btc_vout = 1;
+        let err = btc.relay_deposit_batch(vec![invalid], true).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            app_err("No deposits in batch were credited")
+        );
+
+        let valid = mock_deposit(&txs, 0, 1, dest);
+        btc.relay_deposit_batch(vec![valid], true).unwrap();
+
+        // relaying the same deposit again credits nothing
+        let relayed = mock_deposit(&txs, 0, 1, dest);
+        let err = btc.relay_deposit_batch(vec![relayed], true).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            app_err("No deposits in batch were credited")
+        );
+    }
+
+    fn units_for(config: &Config, value: u64) -> u64 {
+        (value + (9 + P2WPKH_SCRIPT_LEN) * FEE_RATE) * UNITS_PER_SAT + config.withdrawal_fee
+    }
+
+    #[test]
+    fn withdrawal_above_dust() {
+        let config = Config::default();
+        let amount = units_for(&config, DUST_THRESHOLD);
+        let value = calc_withdrawal_value(&config, amount, P2WPKH_SCRIPT_LEN).unwrap();
+        assert_eq!(value, DUST_THRESHOLD);
+    }
+
+    #[test]
+    fn withdrawal_below_dust() {
+        let config = Config::default();
+        let amount = units_for(&config, DUST_THRESHOLD - 1);
+        let err = calc_withdrawal_value(&config, amount, P2WPKH_SCRIPT_LEN).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            OrgaError::App("Withdrawal amount is below dust threshold".to_string()).to_string()
+        );
+    }
+
+    #[test]
+    fn withdrawal_fee_deducted() {
+        let config = Config::default();
+        let amount = 100_000 * UNITS_PER_SAT;
+        let value = calc_withdrawal_value(&config, amount, P2WPKH_SCRIPT_LEN).unwrap();
+        assert_eq!(
+            value,
+            100_000 - WITHDRAWAL_FEE / UNITS_PER_SAT - (9 + P2WPKH_SCRIPT_LEN) * FEE_RATE
+        );
+    }
+
+    #[test]
+    fn withdrawal_uses_peg_config() {
+        let (mut btc, _) = regtest_bitcoin(SignatorySet::mock(&[10]));
+        btc.config.withdrawal_fee = 5_000 * UNITS_PER_SAT;
+        btc.config.dust_threshold = 1_000;
+
+        let signer = Address::from_pubkey([2; 33]);
+        let script = Script::from(vec![0; P2WPKH_SCRIPT_LEN as usize]);
+        let amount = units_for(&btc.config, 1_000);
+        btc.accounts.deposit(signer, Nbtc::mint(amount)).unwrap();
+
+        btc.queue_withdrawal(signer, script, amount.into()).unwrap();
+
+        let reward_pool: u64 = btc.reward_pool.amount.into();
+        assert_eq!(reward_pool, 5_000 * UNITS_PER_SAT);
+        let checkpoint = btc.checkpoints.building().unwrap();
+        assert_eq!(checkpoint.outputs.get(0).unwrap().unwrap().value, 1_000);
+    }
+
+    #[test]
+    fn withdrawal_configured() {
+        let config = Config {
+            withdrawal_fee: 0,
+            dust_threshold: 1_000,
+            ..Default::default()
+        };
+        let amount = units_for(&config, 999);
+        assert!(calc_withdrawal_value(&config, amount, P2WPKH_SCRIPT_LEN).is_err());
+
+        let amount = units_for(&config, 1_000);
+        let value = calc_withdrawal_value(&config, amount, P2WPKH_SCRIPT_LEN).unwrap();
+        assert_eq!(value, 1_000);
+    }
+}
@@ -1,6 +1,9 @@
 use super::SignatorySet;
 use crate::app::App;
-use crate::bitcoin::{adapter::Adapter, header_queue::WrappedHeader};
+use crate::bitcoin::txid_set::Outpoint;
+use crate::bitcoin::{
+    adapter::Adapter, header_queue::WrappedHeader, Deposit, MAX_DEPOSIT_BATCH, MIN_CONFIRMATIONS,
+};
 use crate::error::Result;
 use bitcoincore_rpc_async::bitcoin;
 use bitcoincore_rpc_async::bitcoin::consensus::Encodable;
@@ -15,18 +18,110 @@ use orga::coins::Address;
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::future::Future;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use tokio::sync::mpsc::Receiver;
 use tokio::sync::Mutex;
 use warp::reject;
 
 const HEADER_BATCH_SIZE: usize = 25;
+const APP_CLIENT_RETRIES: u32 = 5;
 
 pub struct Relayer {
     btc_client: BitcoinRpcClient,
     app_client: TendermintClient<App>,
 
     scripts: Option<WatchedScriptStore>,
+    skipped_deposits: HashSet<Outpoint>,
+    status: SharedRelayerStatus,
+}
+
+/// Counters describing the progress of a running relayer, so operators can
+/// check whether it is keeping up. The header, deposit and checkpoint relays
+/// share one status, which is served at `GET /relayer/status`.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct RelayerStatus {
+    pub headers_relayed: u64,
+    pub deposits_relayed: u64,
+    pub last_btc_height: u64,
+    pub last_sidechain_height: u64,
+    pub errors: u64,
+}
+
+pub type SharedRelayerStatus = Arc<RwLock<RelayerStatus>>;
+
+/// Runs a query against the app, replacing `client` with a fresh one from
+/// `connect` if the query fails to reach the node, with exponential backoff.
+/// Errors returned by the app itself are passed through in the inner result
+/// and are not retried.
+async fn retry_query<C, T, F, Fut>(
+    client: &mut C,
+    connect: impl Fn() -> C,
+    query: F,
+) -> Result<orga::Result<T>>
+where
+    C: Clone,
+    F: Fn(C) -> Fut,
+    Fut: Future<Output = orga::Result<orga::Result<T>>>,
+{
+    let mut attempt = 0;
+    loop {
+        match query(client.clone()).await {
+            Ok(res) => return Ok(res),
+            Err(err) if attempt < APP_CLIENT_RETRIES => {
+                let backoff = 1 << attempt;
+                eprintln!(
+                    "App client error: {}. Reconnecting in {} seconds...",
+                    err, backoff
+                );
+                sleep(backoff).await;
+                *client = connect();
+                attempt += 1;
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+/// Runs one pass of a relay loop, logging and counting its error if it fails.
+async fn relay_step<F>(status: &SharedRelayerStatus, name: &str, step: F)
+where
+    F: Future<Output = Result<()>>,
+{
+    if let Err(err) = step.await {
+        eprintln!("{} relay error: {}", name, err);
+        status.write().unwrap().errors += 1;
+    }
+}
+
+/// Checks which deposits of a submitted batch were credited by the app,
+/// counting them in `status`. Deposits the app skipped are added to `skipped`
+/// so later scans don't resubmit them.
+async fn record_credited<C, F, Fut>(
+    client: &mut C,
+    connect: impl Fn() -> C,
+    status: &SharedRelayerStatus,
+    skipped: &mut HashSet<Outpoint>,
+    summaries: Vec<(Outpoint, u64, Address)>,
+    is_processed: F,
+) -> Result<()>
+where
+    C: Clone,
+    F: Fn(C, Outpoint) -> Fut,
+    Fut: Future<Output = orga::Result<orga::Result<bool>>>,
+{
+    for (outpoint, value, dest) in summaries {
+        let credited =
+            retry_query(client, &connect, |client| is_processed(client, outpoint)).await??;
+        if !credited {
+            skipped.insert(outpoint);
+            continue;
+        }
+
+        println!("Relayed deposit: {} sats, {}", value, dest);
+        status.write().unwrap().deposits_relayed += 1;
+    }
+
+    Ok(())
 }
 
 impl Relayer {
@@ -35,22 +130,43 @@ impl Relayer {
             btc_client,
             app_client,
             scripts: None,
+            skipped_deposits: HashSet::new(),
+            status: Default::default(),
         }
     }
 
-    async fn sidechain_block_hash(&self) -> Result<BlockHash> {
-        let hash = self.app_client.bitcoin.headers.hash().await??;
+    /// Replaces the relayer's status with one shared with other relayers.
+    pub fn with_status(mut self, status: SharedRelayerStatus) -> Self {
+        self.status = status;
+        self
+    }
+
+    pub fn status(&self) -> RelayerStatus {
+        self.status.read().unwrap().clone()
+    }
+
+    async fn sidechain_block_hash(&mut self) -> Result<BlockHash> {
+        let hash = self
+            .query_app(|client| async move { client.bitcoin.headers.hash().await })
+            .await??;
         let hash = BlockHash::from_slice(hash.as_slice())?;
         Ok(hash)
     }
 
+    async fn query_app<T, F, Fut>(&mut self, query: F) -> Result<orga::Result<T>>
+    where
+        F: Fn(TendermintClient<App>) -> Fut,
+        Fut: Future<Output = orga::Result<orga::Result<T>>>,
+    {
+        retry_query(&mut self.app_client, crate::app_client, query).await
+    }
+
     pub async fn start_header_relay(&mut self) -> Result<!> {
         println!("Starting header relay...");
 
         loop {
-            if let Err(e) = self.relay_headers().await {
-                eprintln!("Header relay error: {}", e);
-            }
+            let status = self.status.clone();
+            relay_step(&status, "Header", self.relay_headers()).await;
 
             sleep(2).await;
         }
@@ -93,9 +209,8 @@ impl Relayer {
 
         let do_relaying = async {
             loop {
-                if let Err(e) = self.relay_deposits(&mut recv).await {
-                    eprintln!("Deposit relay error: {}", e);
-                }
+                let status = self.status.clone();
+                relay_step(&status, "Deposit", self.relay_deposits(&mut recv)).await;
 
                 sleep(2).await;
             }
@@ -177,7 +292,8 @@ impl Relayer {
             )
             .with(warp::cors().allow_any_origin());
 
-        let server = warp::serve(route).run(([0, 0, 0, 0], 9000));
+        let routes = status_route(self.status.clone()).or(route);
+        let server = warp::serve(routes).run(([0, 0, 0, 0], 9000));
         (server, recv)
     }
 
@@ -209,16 +325,37 @@ impl Relayer {
         let base_height = self.btc_client.get_block_header_info(&tip).await?.height;
         let blocks = self.last_n_blocks(num_blocks, tip).await?;
 
-        for (i, block) in blocks.into_iter().enumerate().rev() {
+        let mut matched = vec![];
+        for (i, block) in blocks.iter().enumerate().rev() {
+            // deposits in less confirmed blocks are picked up by a later scan
+            if i < MIN_CONFIRMATIONS as usize {
+                continue;
+            }
+
             let height = (base_height - i) as u32;
-            for (tx, matches) in self.relevant_txs(&block) {
+            for (tx, matches) in self.relevant_txs(block) {
                 for output in matches {
-                    self.maybe_relay_deposit(tx, height, &block.block_hash(), output)
-                        .await?;
+                    matched.push((tx.clone(), height, block.block_hash(), output));
                 }
             }
         }
 
+        let mut deposits = vec![];
+        for (tx, height, block_hash, output) in matched {
+            if let Some(deposit) = self
+                .unprocessed_deposit(&tx, height, &block_hash, output)
+                .await?
+            {
+                deposits.push(deposit);
+            }
+        }
+
+        while !deposits.is_empty() {
+            let batch_len = deposits.len().min(MAX_DEPOSIT_BATCH);
+            let batch: Vec<_> = deposits.drain(..batch_len).collect();
+            self.relay_deposit_batch(batch).await?;
+        }
+
         Ok(tip)
     }
 
@@ -229,6 +366,7 @@ impl Relayer {
             if let Err(e) = self.relay_checkpoints().await {
                 if !e.to_string().contains("No completed checkpoints yet") {
                     eprintln!("Checkpoint relay error: {}", e);
+                    self.status.write().unwrap().errors += 1;
                 }
             }
 
@@ -238,10 +376,7 @@ impl Relayer {
 
     async fn relay_checkpoints(&mut self) -> Result<()> {
         let last_checkpoint = self
-            .app_client
-            .bitcoin
-            .checkpoints
-            .last_completed_tx()
+            .query_app(|client| async move { client.bitcoin.checkpoints.last_completed_tx().await })
             .await??;
         println!("Last checkpoint tx: {}", last_checkpoint.txid());
 
@@ -249,10 +384,7 @@ impl Relayer {
 
         loop {
             let txs = self
-                .app_client
-                .bitcoin
-                .checkpoints
-                .completed_txs()
+                .query_app(|client| async move { client.bitcoin.checkpoints.completed_txs().await })
                 .await??;
             for tx in txs {
                 if relayed.contains(&tx.txid()) {
@@ -284,10 +416,9 @@ impl Relayer {
     async fn insert_announced_addrs(&mut self, recv: &mut Receiver<(Address, u32)>) -> Result<()> {
         while let Ok((addr, sigset_index)) = recv.try_recv() {
             let checkpoint_res = self
-                .app_client
-                .bitcoin
-                .checkpoints
-                .get(sigset_index)
+                .query_app(
+                    |client| async move { client.bitcoin.checkpoints.get(sigset_index).await },
+                )
                 .await?;
             let sigset = match &checkpoint_res {
                 Ok(checkpoint) => &checkpoint.sigset,
@@ -362,28 +493,31 @@ impl Relayer {
             })
     }
 
-    async fn maybe_relay_deposit(
-        &self,
+    /// Builds a deposit for a matched output, or returns `None` if the output
+    /// has already been relayed.
+    async fn unprocessed_deposit(
+        &mut self,
         tx: &Transaction,
         height: u32,
         block_hash: &BlockHash,
         output: OutputMatch,
-    ) -> Result<()> {
+    ) -> Result<Option<Deposit>> {
         use self::bitcoin::hashes::Hash as _;
 
         let txid = tx.txid();
         let outpoint = (txid.into_inner(), output.vout);
-        let dest = output.dest;
-        let vout = output.vout;
+
+        if self.skipped_deposits.contains(&outpoint) {
+            return Ok(None);
+        }
 
         if self
-            .app_client
-            .bitcoin
-            .processed_outpoints
-            .contains(outpoint)
+            .query_app(|client| async move {
+                client.bitcoin.processed_outpoints.contains(outpoint).await
+            })
             .await??
         {
-            return Ok(());
+            return Ok(None);
         }
 
         let proof_bytes = self
@@ -392,52 +526,62 @@ impl Relayer {
             .await?;
         let proof = ::bitcoin::MerkleBlock::consensus_decode(proof_bytes.as_slice())?.txn;
 
-        {
-            let mut tx_bytes = vec![];
-            tx.consensus_encode(&mut tx_bytes)?;
-            let tx = ::bitcoin::Transaction::consensus_decode(tx_bytes.as_slice())?;
-            let tx = Adapter::new(tx.clone());
-
-            let proof = Adapter::new(proof);
-
-            let res = self
-                .app_client
-                .clone()
-                .pay_from(async move |client| {
-                    client
-                        .bitcoin
-                        .relay_deposit(
-                            tx,
-                            height,
-                            proof,
-                            output.vout,
-                            output.sigset_index,
-                            output.dest,
-                        )
-                        .await
-                })
-                .noop()
-                .await;
-
-            match res {
-                Err(err)
-                    if err.to_string().contains("Deposit amount is below minimum")
-                        || err
-                            .to_string()
-                            .contains("Deposit amount is too small to pay its spending fee") =>
-                {
-                    return Ok(());
-                }
-                _ => res?,
-            };
-        }
+        let mut tx_bytes = vec![];
+        tx.consensus_encode(&mut tx_bytes)?;
+        let tx = ::bitcoin::Transaction::consensus_decode(tx_bytes.as_slice())?;
+
+        Ok(Some(Deposit {
+            btc_tx: Adapter::new(tx),
+            btc_height: height,
+            btc_proof: Adapter::new(proof),
+            btc_vout: output.vout,
+            sigset_index: output.sigset_index,
+            dest: output.dest,
+        }))
+    }
 
-        println!(
-            "Relayed deposit: {} sats, {}",
-            tx.output[vout as usize].value, dest
-        );
+    /// Relays up to `MAX_DEPOSIT_BATCH` deposits in one transaction. Deposits
+    /// which fail verification, e.g. because they are below the minimum
+    /// amount, are skipped by the peg and are not submitted again.
+    async fn relay_deposit_batch(&mut self, deposits: Vec<Deposit>) -> Result<()> {
+        use ::bitcoin::hashes::Hash as _;
 
-        Ok(())
+        let summaries: Vec<_> = deposits
+            .iter()
+            .map(|deposit| {
+                let outpoint = (deposit.btc_tx.txid().into_inner(), deposit.btc_vout);
+                let value = deposit.btc_tx.output[deposit.btc_vout as usize].value;
+                (outpoint, value, deposit.dest)
+            })
+            .collect();
+
+        let res = self
+            .app_client
+            .clone()
+            .pay_from(async move |client| {
+                client.bitcoin.relay_deposits(deposits.into(), true).await
+            })
+            .noop()
+            .await;
+        match res {
+            Err(err)
+                if err
+                    .to_string()
+                    .contains("No deposits in batch were credited") => {}
+            _ => res?,
+        };
+
+        record_credited(
+            &mut self.app_client,
+            crate::app_client,
+            &self.status,
+            &mut self.skipped_deposits,
+            summaries,
+            |client, outpoint| async move {
+                client.bitcoin.processed_outpoints.contains(outpoint).await
+            },
+        )
+        .await
     }
 
     async fn relay_header_batch(
@@ -454,6 +598,12 @@ impl Relayer {
             .get_block_header_info(&sidechain_hash)
             .await?;
 
+        {
+            let mut status = self.status.write().unwrap();
+            status.last_btc_height = fullnode_info.height as u64;
+            status.last_sidechain_height = sidechain_info.height as u64;
+        }
+
         if fullnode_info.height < sidechain_info.height {
             // full node is still syncing
             return Ok(());
@@ -468,11 +618,22 @@ impl Relayer {
             batch[0].height(),
             batch.len(),
         );
+        let batch_len = batch.len() as u64;
+        let batch_tip = batch.last().map(|header| header.height()).unwrap_or(0);
 
+        // not wrapped in retry_query: a call only returns one result, so a
+        // dropped connection can't be told apart from the app rejecting the
+        // batch. the header relay loop's next sidechain_block_hash query
+        // reconnects instead, and the batch is rebuilt from the new tip.
         self.app_client
             .pay_from(async move |client| client.bitcoin.headers.add(batch.into()).await)
             .noop()
             .await?;
+        {
+            let mut status = self.status.write().unwrap();
+            status.headers_relayed += batch_len;
+            status.last_sidechain_height = batch_tip as u64;
+        }
         println!("Relayed headers");
 
         Ok(())
@@ -538,6 +699,15 @@ pub struct OutputMatch {
     dest: Address,
 }
 
+fn status_route(
+    status: SharedRelayerStatus,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    use warp::Filter;
+    warp::get()
+        .and(warp::path!("relayer" / "status"))
+        .map(move || warp::reply::json(&*status.read().unwrap()))
+}
+
 fn time_now() -> u64 {
     use std::time::{SystemTime, UNIX_EPOCH};
     SystemTime::now()
@@ -710,6 +880,141 @@ impl WatchedScriptStore {
     }
 }
 
+#[cfg(test)]
+mod mock_tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Stands in for the app client: a disconnected client fails every query
+    /// like a dropped Tendermint connection would.
+    #[derive(Clone)]
+    struct MockClient {
+        connected: bool,
+    }
+
+    impl MockClient {
+        fn check_connected(&self) -> orga::Result<()> {
+            if !self.connected {
+                return Err(orga::Error::App("connection closed".to_string()));
+            }
+            Ok(())
+        }
+    }
+
+    async fn mock_hash(client: MockClient) -> orga::Result<orga::Result<Vec<u8>>> {
+        client.check_connected()?;
+        Ok(Ok(vec![1, 2, 3]))
+    }
+
+    #[tokio::test]
+    async fn query_recovers_from_dropped_connection() {
+        let connects = AtomicU32::new(0);
+        let connect = || {
+            connects.fetch_add(1, Ordering::SeqCst);
+            MockClient { connected: true }
+        };
+
+        let mut client = MockClient { connected: false };
+        let hash = retry_query(&mut client, connect, mock_hash)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(hash, vec![1, 2, 3]);
+        assert_eq!(connects.load(Ordering::SeqCst), 1);
+        assert!(client.connected);
+    }
+
+    #[tokio::test]
+    async fn query_passes_app_errors_through() {
+        let connects = AtomicU32::new(0);
+        let connect = || {
+            connects.fetch_add(1, Ordering::SeqCst);
+            MockClient { connected: true }
+        };
+
+        let mut client = MockClient { connected: true };
+        let res = retry_query(&mut client, connect, |_| async {
+            Ok::<_, orga::Error>(Err::<(), _>(orga::Error::App("rejected".to_string())))
+        })
+        .await
+        .unwrap();
+        assert!(res.is_err());
+        assert_eq!(connects.load(Ordering::SeqCst), 0);
+    }
+
+    async fn get_status(status: &SharedRelayerStatus) -> serde_json::Value {
+        let res = warp::test::request()
+            .path("/relayer/status")
+            .reply(&status_route(status.clone()))
+            .await;
+        assert_eq!(res.status(), 200);
+        serde_json::from_slice(res.body()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn status_counts_relay_steps() {
+        let status = SharedRelayerStatus::default();
+        let res = get_status(&status).await;
+        assert_eq!(res["deposits_relayed"], 0);
+        assert_eq!(res["errors"], 0);
+
+        // the first deposit was credited, the second was skipped by the app
+        let credited = ([1; 32], 0);
+        let rejected = ([2; 32], 0);
+        let processed: HashSet<_> = vec![credited].into_iter().collect();
+        let summaries = vec![
+            (credited, 10_000, Address::from_pubkey([2; 33])),
+            (rejected, 10_000, Address::from_pubkey([3; 33])),
+        ];
+
+        // the client drops its connection on the first check, so the step
+        // also has to recover through the reconnect path
+        let mut client = MockClient { connected: false };
+        let mut skipped = HashSet::new();
+        relay_step(
+            &status,
+            "Deposit",
+            record_credited(
+                &mut client,
+                || MockClient { connected: true },
+                &status,
+                &mut skipped,
+                summaries,
+                |client, outpoint| {
+                    let credited = processed.contains(&outpoint);
+                    async move {
+                        client.check_connected()?;
+                        Ok(Ok(credited))
+                    }
+                },
+            ),
+        )
+        .await;
+        assert!(skipped.contains(&rejected));
+        assert!(!skipped.contains(&credited));
+
+        // an app error while checking a deposit fails the step
+        let mut client = MockClient { connected: true };
+        relay_step(
+            &status,
+            "Deposit",
+            record_credited(
+                &mut client,
+                || MockClient { connected: true },
+                &status,
+                &mut skipped,
+                vec![(([3; 32], 0), 10_000, Address::from_pubkey([2; 33]))],
+                |_, _| async { Ok(Err(orga::Error::App("unavailable".to_string()))) },
+            ),
+        )
+        .await;
+
+        let res = get_status(&status).await;
+        assert_eq!(res["deposits_relayed"], 1);
+        assert_eq!(res["errors"], 1);
+    }
+}
+
 #[cfg(todo)]
 #[cfg(test)]
 mod tests {
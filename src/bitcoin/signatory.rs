@@ -21,7 +21,18 @@ use super::Xpub;
 
 pub const MAX_DEPOSIT_AGE: u64 = 60 * 60 * 24 * 5;
 pub const MAX_SIGNATORIES: u64 = 20;
+pub const MIN_SIGNATORY_VOTING_POWER: u64 = 1;
 
+/// The voting power signatures must exceed for a set with `total_vp` of voting
+/// power. Both the redeem script and `ThresholdSig` use this, so a checkpoint
+/// is only considered signed once its witness can satisfy the script.
+pub fn signature_threshold(total_vp: u64) -> u64 {
+    ((total_vp as u128) * 2 / 3) as u64
+}
+
+// signatories are ordered by voting power, then by pubkey. field order matters
+// here since the derived `Ord` determines the order of keys in the redeem
+// script.
 #[derive(Encode, Decode, Clone, Debug, PartialOrd, PartialEq, Eq, Ord)]
 pub struct Signatory {
     pub voting_power: u64,
@@ -39,7 +50,12 @@ pub struct SignatorySet {
 
 impl SignatorySet {
     #[cfg(feature = "full")]
-    pub fn from_validator_ctx(index: u32, sig_keys: &Map<ConsensusKey, Xpub>) -> Result<Self> {
+    pub fn from_validator_ctx(
+        index: u32,
+        sig_keys: &Map<ConsensusKey, Xpub>,
+        max_signatories: u64,
+        min_voting_power: u64,
+    ) -> Result<Self> {
         let time: &mut Time = Context::resolve()
             .ok_or_else(|| OrgaError::App("No time context found".to_string()))?;
 
@@ -85,28 +101,60 @@ impl SignatorySet {
             sigset.insert(signatory);
         }
 
-        sigset.sort_and_truncate();
+        sigset.sort_and_truncate(max_signatories, min_voting_power);
 
         Ok(sigset)
     }
 
+    #[cfg(test)]
+    pub(crate) fn mock(voting_powers: &[u64]) -> Self {
+        let mut sigset = SignatorySet {
+            create_time: 0,
+            present_vp: 0,
+            possible_vp: voting_powers.iter().sum(),
+            index: 0,
+            signatories: vec![],
+        };
+
+        for (i, voting_power) in voting_powers.iter().enumerate() {
+            let mut pubkey = [0; 33];
+            pubkey[0] = 2;
+            pubkey[1] = i as u8;
+            sigset.insert(Signatory {
+                voting_power: *voting_power,
+                pubkey: Pubkey::new(pubkey),
+            });
+        }
+
+        sigset
+    }
+
     fn insert(&mut self, signatory: Signatory) {
         self.present_vp += signatory.voting_power;
         self.signatories.push(signatory);
     }
 
-    fn sort_and_truncate(&mut self) {
+    /// Keeps at most `max_signatories` of the highest-powered signatories,
+    /// dropping any with less than `min_voting_power`. Removed voting power is
+    /// subtracted from `present_vp`, so the signature threshold is two-thirds
+    /// of the power actually represented in the set.
+    pub(crate) fn sort_and_truncate(&mut self, max_signatories: u64, min_voting_power: u64) {
         self.signatories.sort_by(|a, b| b.cmp(a));
 
-        if self.signatories.len() as u64 > MAX_SIGNATORIES {
-            for removed in self.signatories.drain(MAX_SIGNATORIES as usize..) {
-                self.present_vp -= removed.voting_power;
-            }
+        let len = self
+            .signatories
+            .iter()
+            .take(max_signatories as usize)
+            .take_while(|signatory| signatory.voting_power >= min_voting_power)
+            .count();
+
+        for removed in self.signatories.drain(len..) {
+            self.present_vp -= removed.voting_power;
         }
     }
 
     pub fn signature_threshold(&self) -> u64 {
-        ((self.present_vp as u128) * 2 / 3) as u64
+        signature_threshold(self.present_vp)
     }
 
     pub fn quorum_threshold(&self) -> u64 {
@@ -215,6 +263,67 @@ impl SignatorySet {
 mod tests {
     use super::*;
 
+    #[test]
+    fn truncate_to_max_signatories() {
+        let mut sigset = SignatorySet::mock(&[10, 50, 20, 40, 30]);
+        sigset.sort_and_truncate(3, 1);
+
+        let voting_powers: Vec<_> = sigset.iter().map(|s| s.voting_power).collect();
+        assert_eq!(voting_powers, vec![50, 40, 30]);
+        assert_eq!(sigset.present_vp(), 120);
+        assert_eq!(sigset.possible_vp(), 150);
+        assert_eq!(sigset.signature_threshold(), 80);
+    }
+
+    #[test]
+    fn truncate_equal_voting_power() {
+        let mut sigset = SignatorySet::mock(&[10; 30]);
+        sigset.sort_and_truncate(MAX_SIGNATORIES, 1);
+
+        assert_eq!(sigset.len(), 20);
+        assert_eq!(sigset.present_vp(), 200);
+        assert_eq!(sigset.possible_vp(), 300);
+        assert_eq!(sigset.signature_threshold(), 133);
+    }
+
+    #[test]
+    fn canonical_order() {
+        let signatories: Vec<_> = SignatorySet::mock(&[10, 30, 10, 20, 30]).signatories;
+
+        let mut a = SignatorySet::mock(&[]);
+        let mut b = SignatorySet::mock(&[]);
+        for signatory in signatories.iter() {
+            a.insert(signatory.clone());
+        }
+        for signatory in signatories.iter().rev() {
+            b.insert(signatory.clone());
+        }
+        a.sort_and_truncate(MAX_SIGNATORIES, 1);
+        b.sort_and_truncate(MAX_SIGNATORIES, 1);
+
+        let a: Vec<_> = a.iter().cloned().collect();
+        let b: Vec<_> = b.iter().cloned().collect();
+        assert_eq!(a, b);
+
+        // ties in voting power are broken by pubkey
+        let order: Vec<_> = a
+            .iter()
+            .map(|s| (s.voting_power, s.pubkey.as_slice()[1]))
+            .collect();
+        assert_eq!(order, vec![(30, 4), (30, 1), (20, 3), (10, 2), (10, 0)]);
+    }
+
+    #[test]
+    fn truncate_below_min_voting_power() {
+        let mut sigset = SignatorySet::mock(&[100, 5, 0, 60, 9]);
+        sigset.sort_and_truncate(MAX_SIGNATORIES, 10);
+
+        let voting_powers: Vec<_> = sigset.iter().map(|s| s.voting_power).collect();
+        assert_eq!(voting_powers, vec![100, 60]);
+        assert_eq!(sigset.present_vp(), 160);
+        assert_eq!(sigset.signature_threshold(), 106);
+    }
+
     // #[test]
     // #[should_panic(expected = "Cannot build script for empty signatory set")]
     // fn redeem_script_empty() {
@@ -0,0 +1,53 @@
+use super::header_queue::{Config, HeaderQueue, WrappedHeader};
+use bitcoin::blockdata::constants::genesis_block;
+use bitcoin::util::merkleblock::PartialMerkleTree;
+use bitcoin::{BlockHeader, TxMerkleNode, Txid};
+use orga::store::{MapStore, Shared, Store};
+
+pub fn mem_store() -> Store {
+    Store::new(Shared::new(MapStore::new()).into())
+}
+
+/// Creates a header queue in `store` which trusts the regtest genesis header.
+pub fn regtest_queue(store: Store) -> (HeaderQueue, WrappedHeader) {
+    let genesis = genesis_block(bitcoin::Network::Regtest).header;
+    let q =
+        HeaderQueue::with_conf(store, Default::default(), Config::regtest(&genesis, 0)).unwrap();
+
+    (q, WrappedHeader::from_header(&genesis, 0))
+}
+
+pub fn mine_header_with_root(
+    prev: &WrappedHeader,
+    merkle_root: TxMerkleNode,
+    spacing: u32,
+) -> BlockHeader {
+    let mut header = BlockHeader {
+        version: 0x1,
+        prev_blockhash: prev.block_hash(),
+        merkle_root,
+        time: prev.time() + spacing,
+        bits: prev.bits(),
+        nonce: 0,
+    };
+
+    while header.validate_pow(&header.target()).is_err() {
+        header.nonce += 1;
+    }
+
+    header
+}
+
+/// Mines a block containing `txids` on top of `prev` and adds its header to
+/// the queue.
+pub fn mine_block(q: &mut HeaderQueue, prev: &WrappedHeader, txids: &[Txid]) -> WrappedHeader {
+    let merkle_root = PartialMerkleTree::from_txids(txids, &vec![true; txids.len()])
+        .extract_matches(&mut vec![], &mut vec![])
+        .unwrap();
+
+    let header = mine_header_with_root(prev, merkle_root, 10 * 60);
+    let header = WrappedHeader::from_header(&header, prev.height() + 1);
+    q.add_into_iter([header.clone()]).unwrap();
+
+    header
+}
@@ -1,3 +1,4 @@
+use super::signatory::signature_threshold;
 use super::SignatorySet;
 use bitcoin::blockdata::transaction::EcdsaSighashType;
 use bitcoin::secp256k1::{
@@ -102,8 +103,7 @@ impl ThresholdSig {
             total_vp += signatory.voting_power;
         }
 
-        // TODO: get threshold ratio from somewhere else
-        self.threshold = ((total_vp as u128) * 2 / 3) as u64;
+        self.threshold = signature_threshold(total_vp);
 
         Ok(())
     }
@@ -119,8 +119,7 @@ impl ThresholdSig {
             self.sigs.insert(pubkey, share.into())?;
         }
 
-        // TODO: get threshold ratio from somewhere else
-        self.threshold = ((total_vp as u128) * 2 / 3) as u64;
+        self.threshold = signature_threshold(total_vp);
         self.len = len;
 
         Ok(())
@@ -128,7 +127,8 @@ impl ThresholdSig {
 
     #[query]
     pub fn done(&self) -> bool {
-        self.signed >= self.threshold
+        // the redeem script checks the signed power with OP_GREATERTHAN
+        self.signed > self.threshold
     }
 
     #[query]
@@ -337,3 +337,29 @@ where
         Ok(LengthVec { len, values })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::signatory::MAX_SIGNATORIES;
+    use crate::bitcoin::test_utils::mem_store;
+
+    #[test]
+    fn truncated_sigset_can_complete() {
+        let mut sigset = SignatorySet::mock(&[10; 30]);
+        sigset.sort_and_truncate(MAX_SIGNATORIES, 1);
+
+        let store = mem_store();
+        let mut sig = ThresholdSig::create(store, Default::default()).unwrap();
+        sig.from_sigset(&sigset).unwrap();
+        assert_eq!(sig.len(), 20);
+        assert_eq!(sig.threshold, sigset.signature_threshold());
+
+        // exactly the threshold does not satisfy the redeem script
+        sig.signed = sigset.signature_threshold();
+        assert!(!sig.done());
+
+        sig.signed = sigset.present_vp();
+        assert!(sig.done());
+    }
+}
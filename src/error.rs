@@ -32,3 +32,39 @@ pub enum Error {
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
+
+impl From<Error> for orga::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Orga(inner) => inner,
+            err => orga::Error::App(err.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header_error() -> Result<()> {
+        Err(Error::Header("Header not found".into()))
+    }
+
+    fn orga_layer(res: Result<()>) -> orga::Result<()> {
+        res?;
+        Ok(())
+    }
+
+    #[test]
+    fn bitcoin_error_into_orga_error() {
+        let err = orga_layer(header_error()).unwrap_err();
+        assert!(matches!(err, orga::Error::App(msg) if msg == "Header not found"));
+    }
+
+    #[test]
+    fn wrapped_orga_error_is_unwrapped() {
+        let res = Err(Error::Orga(orga::Error::App("inner".into())));
+        let err = orga_layer(res).unwrap_err();
+        assert!(matches!(err, orga::Error::App(msg) if msg == "inner"));
+    }
+}